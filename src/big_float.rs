@@ -0,0 +1,652 @@
+//! Arbitrary precision sibling of the SoftFloat.
+//! Significand is an array of LIMBS u64 words, so 128, 256, 512... bits of precision,
+//! everything lives on the stack, no allocations like in the rug case.
+//!
+//! Exponent is i64, that is so wide, it does not overflow for anything fractal related,
+//! hence there are no subnormals, infinities nor NaNs here.
+//! Rounding is the same as in the SoftFloat - to nearest, ties to even.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, AddAssign, Mul, MulAssign, Sub, SubAssign};
+
+use crate::fractals::{Abs, Epsilon};
+
+const LIMB_WIDTH: u32 = u64::BITS;
+const LIMB_TOP_BIT: u64 = 1 << (LIMB_WIDTH - 1);
+
+// binary64 layout, needed for conversions from and to f64
+const F64_BIAS: i64 = 1023;
+const F64_EXPONENT_MASK: u64 = 0x7FF;
+const F64_SIGNIFICAND_WIDTH: u32 = 52;
+const F64_SIGNIFICAND_MASK: u64 = 0xFFFFFFFFFFFFF;
+
+/// Value of the number is 0.significand * 2^exponent, limbs are little endian.
+/// Significand is always normalized - most significant bit of the last limb is set,
+/// unless the number is zero.
+// clone + copy to be able to do: x + x etc.
+#[derive(Debug, Clone, Copy)]
+pub struct BigFloat<const LIMBS: usize> {
+    negative: bool,
+    exponent: i64,
+    significand: [u64; LIMBS],
+}
+
+/// Significand extended with one limb below, it keeps guard and round bits,
+/// sticky bit is OR-ed into the least significant bit of it.
+/// Limb with index 0 is the extra one.
+#[derive(Clone, Copy)]
+struct Extended<const LIMBS: usize> {
+    significand: [u64; LIMBS],
+    guard: u64,
+}
+
+impl<const LIMBS: usize> Extended<LIMBS> {
+    const LEN: usize = LIMBS + 1;
+
+    fn new(significand: [u64; LIMBS]) -> Self {
+        Self {
+            significand,
+            guard: 0,
+        }
+    }
+
+    fn get(&self, i: usize) -> u64 {
+        if i == 0 {
+            self.guard
+        } else {
+            self.significand[i - 1]
+        }
+    }
+
+    fn set(&mut self, i: usize, limb: u64) {
+        if i == 0 {
+            self.guard = limb;
+        } else {
+            self.significand[i - 1] = limb;
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.guard == 0 && self.significand.iter().all(|&limb| limb == 0)
+    }
+
+    fn leading_zeros(&self) -> u32 {
+        let mut zeros = 0;
+        for i in (0..Self::LEN).rev() {
+            let limb = self.get(i);
+            zeros += limb.leading_zeros();
+
+            if limb != 0 {
+                break;
+            }
+        }
+
+        zeros
+    }
+
+    /// Shift right, bits falling off stick to the least significant bit
+    fn shift_right_sticky(&mut self, shift: u32) {
+        if shift == 0 {
+            return;
+        }
+
+        if shift as usize >= Self::LEN * LIMB_WIDTH as usize {
+            let sticky = !self.is_zero();
+            *self = Self::new([0; LIMBS]);
+            self.guard = sticky as u64;
+            return;
+        }
+
+        let limbs = (shift / LIMB_WIDTH) as usize;
+        let bits = shift % LIMB_WIDTH;
+
+        let mut sticky = (0..limbs).any(|i| self.get(i) != 0);
+        if bits > 0 {
+            sticky |= self.get(limbs) & ((1 << bits) - 1) != 0;
+        }
+
+        for i in 0..Self::LEN {
+            let src = i + limbs;
+            let mut limb = if src < Self::LEN {
+                self.get(src) >> bits
+            } else {
+                0
+            };
+
+            if bits > 0 && src + 1 < Self::LEN {
+                limb |= self.get(src + 1) << (LIMB_WIDTH - bits);
+            }
+
+            self.set(i, limb);
+        }
+
+        self.guard |= sticky as u64;
+    }
+
+    fn shift_left(&mut self, shift: u32) {
+        if shift == 0 {
+            return;
+        }
+
+        let limbs = (shift / LIMB_WIDTH) as usize;
+        let bits = shift % LIMB_WIDTH;
+
+        for i in (0..Self::LEN).rev() {
+            let mut limb = if i >= limbs {
+                self.get(i - limbs) << bits
+            } else {
+                0
+            };
+
+            if bits > 0 && i > limbs {
+                limb |= self.get(i - limbs - 1) >> (LIMB_WIDTH - bits);
+            }
+
+            self.set(i, limb);
+        }
+    }
+
+    /// Returns carry
+    fn add(&mut self, rhs: &Self) -> bool {
+        let mut carry = false;
+
+        for i in 0..Self::LEN {
+            let (sum, c1) = self.get(i).overflowing_add(rhs.get(i));
+            let (sum, c2) = sum.overflowing_add(carry as u64);
+            self.set(i, sum);
+            carry = c1 || c2;
+        }
+
+        carry
+    }
+
+    /// Self has to be greater or equal to rhs
+    fn sub(&mut self, rhs: &Self) {
+        let mut borrow = false;
+
+        for i in 0..Self::LEN {
+            let (diff, b1) = self.get(i).overflowing_sub(rhs.get(i));
+            let (diff, b2) = diff.overflowing_sub(borrow as u64);
+            self.set(i, diff);
+            borrow = b1 || b2;
+        }
+    }
+}
+
+impl<const LIMBS: usize> BigFloat<LIMBS> {
+    pub fn zero(negative: bool) -> Self {
+        Self {
+            negative,
+            exponent: 0,
+            significand: [0; LIMBS],
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        // Normalized, so it is enough to check the most significant limb
+        self.significand[LIMBS - 1] == 0
+    }
+
+    /// Precision in bits
+    pub fn precision() -> u32 {
+        LIMBS as u32 * LIMB_WIDTH
+    }
+
+    /// Builds the number out of the result of an operation, the single place
+    /// where normalization and rounding happens.
+    /// Value is 0.significand * 2^exponent, binary point is just above the most significant limb,
+    /// but leading one can be anywhere.
+    fn round_pack(negative: bool, mut exponent: i64, mut significand: Extended<LIMBS>) -> Self {
+        if significand.is_zero() {
+            return Self::zero(negative);
+        }
+
+        let leading_zeros = significand.leading_zeros();
+        significand.shift_left(leading_zeros);
+        exponent -= leading_zeros as i64;
+
+        // Guard limb: 100..0 is a tie, round to even. Above it, round up.
+        let guard = significand.guard;
+        let mut significand = significand.significand;
+
+        if guard > LIMB_TOP_BIT || (guard == LIMB_TOP_BIT && significand[0] & 1 == 1) {
+            let mut carry = true;
+            for limb in significand.iter_mut() {
+                let (sum, c) = limb.overflowing_add(carry as u64);
+                *limb = sum;
+                carry = c;
+
+                if !carry {
+                    break;
+                }
+            }
+
+            if carry {
+                // 0.11...1 + 0.00...1 = 1.00...0
+                significand[LIMBS - 1] = LIMB_TOP_BIT;
+                exponent += 1;
+            }
+        }
+
+        Self {
+            negative,
+            exponent,
+            significand,
+        }
+    }
+
+    fn magnitude_cmp(&self, other: &Self) -> Ordering {
+        match (self.is_zero(), other.is_zero()) {
+            (true, true) => return Ordering::Equal,
+            (true, false) => return Ordering::Less,
+            (false, true) => return Ordering::Greater,
+            _ => (),
+        }
+
+        // Limbs are little endian, compare starting from the most significant one
+        self.exponent.cmp(&other.exponent).then_with(|| {
+            self.significand
+                .iter()
+                .rev()
+                .cmp(other.significand.iter().rev())
+        })
+    }
+}
+
+impl<const LIMBS: usize> Default for BigFloat<LIMBS> {
+    fn default() -> Self {
+        Self::zero(false)
+    }
+}
+
+/// Panics on infinities and NaNs, BigFloat has neither.
+/// Floating needs From<f64>, and kernels never convert those.
+impl<const LIMBS: usize> From<f64> for BigFloat<LIMBS> {
+    fn from(a: f64) -> Self {
+        let bits = a.to_bits();
+
+        let negative = (bits >> 63) == 1;
+        let exponent = (bits >> F64_SIGNIFICAND_WIDTH) & F64_EXPONENT_MASK;
+        let significand = bits & F64_SIGNIFICAND_MASK;
+
+        assert!(
+            exponent != F64_EXPONENT_MASK,
+            "BigFloat does not support infinities nor NaNs"
+        );
+
+        // Subnormals have the same scale as the smallest normal number
+        let (exponent, significand) = if exponent == 0 {
+            (1, significand)
+        } else {
+            (exponent as i64, significand | (1 << F64_SIGNIFICAND_WIDTH))
+        };
+
+        // f64 value is significand * 2^(exponent - bias - 52), but here binary point is
+        // above the most significant limb, that is 64 bits above the significand.
+        let mut extended = Extended::new([0; LIMBS]);
+        extended.significand[LIMBS - 1] = significand;
+        let exponent = exponent - F64_BIAS - F64_SIGNIFICAND_WIDTH as i64 + LIMB_WIDTH as i64;
+
+        Self::round_pack(negative, exponent, extended)
+    }
+}
+
+impl<const LIMBS: usize> From<BigFloat<LIMBS>> for f64 {
+    fn from(a: BigFloat<LIMBS>) -> Self {
+        if a.is_zero() {
+            return if a.negative { -0.0 } else { 0.0 };
+        }
+
+        // Most significant limb is more than f64 can hold, the rest of limbs only matters
+        // for rounding, so fold them into sticky bit. Conversion from u64 rounds to nearest even.
+        let sticky = a.significand[..LIMBS - 1].iter().any(|&limb| limb != 0);
+        let value = (a.significand[LIMBS - 1] | sticky as u64) as f64;
+
+        let exponent = (a.exponent - LIMB_WIDTH as i64).clamp(i32::MIN as i64, i32::MAX as i64);
+        // powi would underflow too early for subnormal results, so split the scaling in two
+        let value = value
+            * 2.0f64.powi(exponent as i32 / 2)
+            * 2.0f64.powi(exponent as i32 - exponent as i32 / 2);
+
+        if a.negative {
+            -value
+        } else {
+            value
+        }
+    }
+}
+
+/// Get nice "float like" representation
+impl<const LIMBS: usize> fmt::Display for BigFloat<LIMBS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Same as in the SoftFloat, go the easiest way. Printing all the digits
+        // requires big int division.
+        write!(f, "{}", f64::from(*self))
+    }
+}
+
+impl<const LIMBS: usize> Add for BigFloat<LIMBS> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        // Make sure 'a' has greater magnitude
+        let (a, b) = if self.magnitude_cmp(&rhs) == Ordering::Less {
+            (rhs, self)
+        } else {
+            (self, rhs)
+        };
+
+        if b.is_zero() {
+            if a.is_zero() && a.negative != b.negative {
+                // -0 + 0 = +0
+                return Self::zero(false);
+            }
+
+            return a;
+        }
+
+        // Align smaller number to the exponent of the bigger one.
+        // Bits shifted out are not lost, they stick to the guard limb.
+        let mut a_sig = Extended::new(a.significand);
+        let mut b_sig = Extended::new(b.significand);
+        let shift = (a.exponent - b.exponent).min(u32::MAX as i64) as u32;
+        b_sig.shift_right_sticky(shift);
+
+        if a.negative == b.negative {
+            let mut exponent = a.exponent;
+
+            if a_sig.add(&b_sig) {
+                // Carry went out of the significand, make a room for it
+                a_sig.shift_right_sticky(1);
+                a_sig.significand[LIMBS - 1] |= LIMB_TOP_BIT;
+                exponent += 1;
+            }
+
+            Self::round_pack(a.negative, exponent, a_sig)
+        } else {
+            a_sig.sub(&b_sig);
+
+            if a_sig.is_zero() {
+                // x - x is +0 when rounding to nearest
+                return Self::zero(false);
+            }
+
+            // Leading one went down, round_pack shifts it back
+            Self::round_pack(a.negative, a.exponent, a_sig)
+        }
+    }
+}
+
+impl<const LIMBS: usize> AddAssign for BigFloat<LIMBS> {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl<const LIMBS: usize> Sub for BigFloat<LIMBS> {
+    type Output = Self;
+
+    fn sub(self, mut rhs: Self) -> Self::Output {
+        // a - b = a + (-b)
+        rhs.negative = !rhs.negative;
+
+        self + rhs
+    }
+}
+
+impl<const LIMBS: usize> SubAssign for BigFloat<LIMBS> {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+impl<const LIMBS: usize> Mul for BigFloat<LIMBS> {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        let negative = self.negative ^ rhs.negative;
+
+        if self.is_zero() || rhs.is_zero() {
+            return Self::zero(negative);
+        }
+
+        // Schoolbook multiplication, only the upper half of the product is needed
+        // as the result, limb below it is the guard, everything lower is sticky.
+        // Iterate over the product limbs from the least significant one, to
+        // propagate carries as we go.
+        let mut result = Extended::new([0; LIMBS]);
+        let mut sticky = false;
+
+        // Product has 2 * LIMBS limbs, 'carry' keeps two limbs worth of a column sum
+        let mut carry: u128 = 0;
+        for column in 0..2 * LIMBS {
+            let mut sum = carry;
+            let mut overflow: u128 = 0;
+
+            let first = column.saturating_sub(LIMBS - 1);
+            for i in first..=column.min(LIMBS - 1) {
+                let product = self.significand[i] as u128 * rhs.significand[column - i] as u128;
+                let (s, o) = sum.overflowing_add(product);
+                sum = s;
+                overflow += o as u128;
+            }
+
+            let limb = sum as u64;
+            carry = (sum >> LIMB_WIDTH) | (overflow << LIMB_WIDTH);
+
+            if column + 1 < LIMBS {
+                sticky |= limb != 0;
+            } else {
+                result.set(column + 1 - LIMBS, limb);
+            }
+        }
+
+        result.guard |= sticky as u64;
+
+        // 0.1xx * 0.1xx is at least 0.01, round_pack normalizes it
+        Self::round_pack(negative, self.exponent + rhs.exponent, result)
+    }
+}
+
+impl<const LIMBS: usize> MulAssign for BigFloat<LIMBS> {
+    fn mul_assign(&mut self, rhs: Self) {
+        *self = *self * rhs;
+    }
+}
+
+impl<const LIMBS: usize> Epsilon for BigFloat<LIMBS> {
+    fn epsilon() -> Self {
+        // 1.0 is 0.1 * 2^1, next number differs on the last bit of significand
+        let mut significand = [0; LIMBS];
+        significand[LIMBS - 1] = LIMB_TOP_BIT;
+
+        Self {
+            negative: false,
+            exponent: 2 - Self::precision() as i64,
+            significand,
+        }
+    }
+}
+
+impl<const LIMBS: usize> Abs for BigFloat<LIMBS> {
+    fn abs(self) -> Self {
+        Self {
+            negative: false,
+            ..self
+        }
+    }
+}
+
+/// Agrees with the comparison, +0 == -0, as in f64
+impl<const LIMBS: usize> PartialEq for BigFloat<LIMBS> {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl<const LIMBS: usize> PartialOrd for BigFloat<LIMBS> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        // +0 == -0
+        if self.is_zero() && other.is_zero() {
+            return Some(Ordering::Equal);
+        }
+
+        let ordering = match (self.negative, other.negative) {
+            (false, false) => self.magnitude_cmp(other),
+            (true, true) => other.magnitude_cmp(self),
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+        };
+
+        Some(ordering)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALUES: [f64; 16] = [
+        0.0,
+        1.0,
+        -1.0,
+        0.1,
+        -0.7436438870371587,
+        0.13182590420531198,
+        2.875,
+        -25.0,
+        0.00046,
+        12345413.0543223,
+        -0.000000000000000038614262509059454,
+        1e-300,
+        -1e300,
+        5e-324,
+        2.2250738585072014e-308,
+        4.0,
+    ];
+
+    #[test]
+    fn convert_from_and_to_double_works() {
+        for a in VALUES {
+            assert_eq!(f64::from(BigFloat::<1>::from(a)), a);
+            assert_eq!(f64::from(BigFloat::<2>::from(a)), a);
+            assert_eq!(f64::from(BigFloat::<8>::from(a)), a);
+        }
+
+        assert_eq!(format!("{}", BigFloat::<4>::from(-0.1)), "-0.1");
+    }
+
+    #[test]
+    fn multiplication_is_exact() {
+        // f64 * f64 fits in 106 bits, two limbs have to get it right.
+        // Exact result is p + e, where e is the error of f64 multiplication.
+        for a in VALUES {
+            for b in VALUES {
+                let p = a * b;
+                if !p.is_normal() || p.abs() > 1e290 || p.abs() < 1e-290 {
+                    continue;
+                }
+                let e = a.mul_add(b, -p);
+
+                let product = BigFloat::<2>::from(a) * BigFloat::<2>::from(b);
+
+                assert_eq!(f64::from(product), p);
+                assert_eq!(f64::from(product - BigFloat::<2>::from(p)), e);
+            }
+        }
+
+        // Three f64 multiplied need 159 bits, so with 256 bits order does not matter
+        let (a, b, c) = (
+            BigFloat::<4>::from(0.1),
+            BigFloat::<4>::from(-0.7436438870371587),
+            BigFloat::<4>::from(12345413.0543223),
+        );
+        assert_eq!((a * b) * c, a * (b * c));
+    }
+
+    #[test]
+    fn addition_is_exact() {
+        // Knuth's two sum: a + b = s + e exactly
+        for a in VALUES {
+            for b in VALUES {
+                if (a.abs().max(b.abs()) / a.abs().min(b.abs())) > 1e20 {
+                    // Result would need more than 128 bits, 53 + log2(1e20) is still fine
+                    continue;
+                }
+
+                let s = a + b;
+                let v = s - a;
+                let e = (a - (s - v)) + (b - v);
+
+                let sum = BigFloat::<2>::from(a) + BigFloat::<2>::from(b);
+                assert_eq!(f64::from(sum - BigFloat::<2>::from(s)), e);
+
+                let diff = BigFloat::<2>::from(a) - BigFloat::<2>::from(-b);
+                assert_eq!(diff, sum);
+            }
+        }
+    }
+
+    #[test]
+    fn rounding_works() {
+        let one = BigFloat::<1>::from(1.0);
+        let ulp = BigFloat::<1>::from(2.0f64.powi(-63));
+        let half_ulp = BigFloat::<1>::from(2.0f64.powi(-64));
+        let quarter_ulp = BigFloat::<1>::from(2.0f64.powi(-65));
+
+        // Tie, round to even
+        assert_eq!(one + half_ulp, one);
+        assert_eq!((one + ulp) + half_ulp, one + ulp + ulp);
+        // Above half, round up
+        assert_eq!(one + half_ulp + quarter_ulp, one);
+        assert_eq!(one + (half_ulp + quarter_ulp), one + ulp);
+        // Below half, round down
+        assert_eq!(one + quarter_ulp, one);
+        // Below one ulp is two times smaller, so that one is a tie again
+        assert_eq!(one - quarter_ulp, one);
+        // Borrow from the sticky bit
+        let below_tie = BigFloat::<1>::from(2.0f64.powi(-65) + 2.0f64.powi(-70));
+        assert_eq!(one - below_tie, one - half_ulp);
+
+        // Same thing with more limbs
+        let one = BigFloat::<4>::from(1.0);
+        let tiny = BigFloat::<4>::from(2.0f64.powi(-200));
+        assert!(one + tiny > one);
+        assert_eq!((one + tiny) - one, tiny);
+        assert_eq!(one + tiny * tiny, one);
+    }
+
+    #[test]
+    fn zeros_work() {
+        let zero = BigFloat::<2>::zero(false);
+        let neg_zero = BigFloat::<2>::zero(true);
+        let one = BigFloat::<2>::from(1.0);
+        // Zeros are equal, whatever the sign, bits of f64 tell them apart
+        let bits = |a: BigFloat<2>| f64::from(a).to_bits();
+
+        assert_eq!(bits(one - one), bits(zero));
+        assert_eq!(bits(neg_zero + neg_zero), bits(neg_zero));
+        assert_eq!(bits(neg_zero + zero), bits(zero));
+        assert_eq!(bits(one * neg_zero), bits(neg_zero));
+        assert_eq!(bits(neg_zero.abs()), bits(zero));
+        assert_eq!(zero + one, one);
+        assert!(zero == zero);
+        assert!(zero == neg_zero);
+        assert!(zero <= neg_zero);
+    }
+
+    #[test]
+    fn comparsion_works() {
+        for a in VALUES {
+            for b in VALUES {
+                assert_eq!(
+                    BigFloat::<4>::from(a).partial_cmp(&BigFloat::<4>::from(b)),
+                    a.partial_cmp(&b)
+                );
+            }
+
+            assert_eq!(BigFloat::<4>::from(a).abs(), BigFloat::<4>::from(a.abs()));
+        }
+    }
+}
@@ -0,0 +1,860 @@
+///! Turns the outcome of the iteration into colors.
+///! Some colorings need more than the iteration count, kernels are asked to compute it
+///! only when the coloring in use needs it. Others need the whole frame first,
+///! Colorizer is made out of it.
+use image::Rgb;
+use std::f64::consts::TAU;
+
+use crate::{
+    fractal_builder::Context,
+    fractals::{color_rainbow, Floating, PixelResult},
+    palette::Palette,
+};
+
+/// How pixels outside of the set are colored. Hues come from the context.palette,
+/// if there is one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(dead_code)]
+pub enum Coloring {
+    /// Hue goes round with the iteration count
+    Rainbow,
+    /// Boundary of the set drawn as black lines on white, out of the distance estimate.
+    /// Pixels closer to the set than that many pixels are darkened, filaments thinner
+    /// than a pixel stay visible.
+    Distance(f64),
+    /// Rainbow, lit as a 3D surface with the normal map out of the derivative dz/dc.
+    /// Light comes from the angle, in degrees, counterclockwise from the positive real axis,
+    /// the higher it is above the plane, the flatter the relief looks.
+    Lighting { angle: f64, height: f64 },
+    /// Hue by the percentile of the iteration count among the escaped pixels of the frame,
+    /// so colors stay spread evenly, whatever the zoom and the limit
+    Histogram,
+    /// Orbits which come closer to the trap than size are colored by the iteration it happened,
+    /// brighter the closer they got, others are black
+    Trap { trap: Trap, size: f64 },
+    /// Hue by the average of sin(density * arg z) along the orbit, stripes run
+    /// out of the set along the external rays, density of them is a whole number
+    Stripe(f64),
+    /// Hue by the triangle inequality average, where |z| lands between the bounds |z^2| and |c|
+    /// give it, along the orbit
+    TriangleInequality,
+    /// Binary decomposition, white where z escaped above the real axis, black below it.
+    /// Edges of the cells line up along the external rays.
+    Decomposition,
+    /// Hue by the argument of z the orbit escaped at, one turn is one repetition
+    /// of the gradient, field lines run out of the set where the hue changes
+    ExternalAngle,
+}
+
+/// Shape on the complex plane, kernels record how close the orbit came to it
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(dead_code)]
+pub enum Trap {
+    Point(f64, f64),
+    /// Angle in degrees, counterclockwise from the real axis
+    Line {
+        through: (f64, f64),
+        angle: f64,
+    },
+    /// Two lines parallel to the axes, crossing at the point. At the origin, Pickover stalks.
+    Cross(f64, f64),
+    Circle {
+        center: (f64, f64),
+        radius: f64,
+    },
+}
+
+/// Term the kernels average along the orbit, each one is within 0..1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Average {
+    Stripe(f64),
+    TriangleInequality,
+}
+
+/// How pixels inside of the set are colored, out of the attracting cycle the orbit fell into.
+/// Pixels which cycle was not found before the limit are black anyway.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(dead_code)]
+pub enum Interior {
+    /// Flat black, nothing to compute
+    Black,
+    /// Hue by the period of the component, brightness by the multiplier,
+    /// black at the nucleus, bright at the boundary
+    Multiplier,
+    /// Same as Coloring::Distance, but from the inside, with the interior distance estimate
+    Distance(f64),
+}
+
+impl Coloring {
+    /// If kernels have to track the derivative dz/dc, and give the distance estimate
+    pub fn needs_derivative(&self) -> bool {
+        match self {
+            Coloring::Rainbow
+            | Coloring::Histogram
+            | Coloring::Trap { .. }
+            | Coloring::Stripe(_)
+            | Coloring::TriangleInequality
+            | Coloring::Decomposition
+            | Coloring::ExternalAngle => false,
+            Coloring::Distance(_) | Coloring::Lighting { .. } => true,
+        }
+    }
+
+    /// Trap the kernels have to measure the orbit against, if any
+    pub fn trap(&self) -> Option<Trap> {
+        match self {
+            Coloring::Trap { trap, .. } => Some(*trap),
+            _ => None,
+        }
+    }
+
+    /// Term the kernels have to average along the orbit, if any
+    pub fn average(&self) -> Option<Average> {
+        match self {
+            Coloring::Stripe(density) => Some(Average::Stripe(*density)),
+            Coloring::TriangleInequality => Some(Average::TriangleInequality),
+            _ => None,
+        }
+    }
+
+    /// If kernels have to look at every point of the orbit, not only at the last one
+    pub fn needs_orbit(&self) -> bool {
+        self.trap().is_some() || self.average().is_some()
+    }
+
+    /// If kernels have to give the z the orbit escaped at
+    pub fn needs_escape_point(&self) -> bool {
+        match self {
+            Coloring::Decomposition | Coloring::ExternalAngle => true,
+            _ => false,
+        }
+    }
+
+    /// If the orbit has to get far away from the set before it counts as escaped,
+    /// for the last points of it to settle
+    pub fn needs_far_bailout(&self) -> bool {
+        self.needs_derivative() || self.average().is_some() || self.needs_escape_point()
+    }
+}
+
+impl Trap {
+    pub fn distance(&self, x: f64, y: f64) -> f64 {
+        match *self {
+            Trap::Point(px, py) => (x - px).hypot(y - py),
+            Trap::Line {
+                through: (px, py),
+                angle,
+            } => {
+                // Cross product with the unit direction of the line
+                let (sin, cos) = angle.to_radians().sin_cos();
+                ((x - px) * sin - (y - py) * cos).abs()
+            }
+            Trap::Cross(px, py) => (x - px).abs().min((y - py).abs()),
+            Trap::Circle {
+                center: (px, py),
+                radius,
+            } => ((x - px).hypot(y - py) - radius).abs(),
+        }
+    }
+}
+
+impl Interior {
+    /// If kernels have to find the cycle of every interior pixel, instead of taking shortcuts,
+    /// and give its multiplier and the distance estimate
+    pub fn needs_cycle(&self) -> bool {
+        match self {
+            Interior::Black => false,
+            Interior::Multiplier | Interior::Distance(_) => true,
+        }
+    }
+}
+
+/// Colorings giving fractions, histogram and averages, spread them over that part of the
+/// gradient, from red to magenta of the rainbow, not all the way round, so both ends
+/// do not get the same color
+const FRACTION_SPREAD: f64 = 300.0 / 360.0;
+
+/// Colors pixels of one frame
+pub struct Colorizer<'a, F> {
+    context: &'a Context<F>,
+    /// Fraction of the escaped pixels, which escaped at or before the iteration
+    cumulative: Vec<f64>,
+}
+
+impl<'a, F: Floating> Colorizer<'a, F> {
+    /// Pixels are the frame, or a sample of it, only colorings which need them look at them
+    pub fn new<'p>(
+        context: &'a Context<F>,
+        pixels: impl IntoIterator<Item = &'p PixelResult>,
+    ) -> Self {
+        let mut cumulative = vec![];
+
+        if context.coloring == Coloring::Histogram {
+            let limit = context.poi.limit;
+            let mut counts = vec![0u64; limit as usize];
+            let mut total = 0;
+
+            for pixel in pixels.into_iter().filter(|pixel| pixel.iteration < limit) {
+                counts[pixel.iteration as usize] += 1;
+                total += 1;
+            }
+
+            let mut sum = 0;
+            cumulative = counts
+                .iter()
+                .map(|count| {
+                    sum += count;
+                    sum as f64 / total.max(1) as f64
+                })
+                .collect();
+        }
+
+        Colorizer {
+            context,
+            cumulative,
+        }
+    }
+
+    /// Exterior and interior colorings are picked separately, pixel is colored by one of them
+    pub fn color(&self, pixel: &PixelResult) -> Rgb<u8> {
+        let context = self.context;
+        let pixel_size = || context.poi.pinhole_size.into() / context.img_width as f64;
+
+        if pixel.iteration == context.poi.limit {
+            return match context.interior {
+                _ if pixel.period == 0 => Rgb([0, 0, 0]),
+                Interior::Black => Rgb([0, 0, 0]),
+                Interior::Multiplier => {
+                    // Hues of components of neighbouring periods are far apart
+                    dim(self.gradient((pixel.period * 77) as f64), pixel.multiplier)
+                }
+                Interior::Distance(thickness) => shade(pixel.distance / pixel_size() / thickness),
+            };
+        }
+
+        match context.coloring {
+            // Colors straight from the gradient, exterior pixels always have the position
+            Coloring::Rainbow
+            | Coloring::Histogram
+            | Coloring::Stripe(_)
+            | Coloring::TriangleInequality
+            | Coloring::ExternalAngle => self.gradient(self.position(pixel).unwrap()),
+            Coloring::Distance(thickness) => shade(pixel.distance / pixel_size() / thickness),
+            Coloring::Lighting { angle, height } => {
+                let (light_x, light_y) = (angle.to_radians().cos(), angle.to_radians().sin());
+
+                // Part of the normal along the plane faces the light or not, height lifts it,
+                // so the surface facing away is not black, and it is scaled back to 0..1
+                let reflection =
+                    (pixel.normal.0 * light_x + pixel.normal.1 * light_y + height) / (1.0 + height);
+
+                dim(self.gradient(pixel.iteration as f64), reflection.max(0.0))
+            }
+            Coloring::Trap { size, .. } => {
+                let closeness = 1.0 - pixel.trap_distance / size;
+
+                dim(
+                    self.gradient(pixel.trap_iteration as f64),
+                    closeness.max(0.0),
+                )
+            }
+            Coloring::Decomposition if pixel.escape.1 >= 0.0 => Rgb([255, 255, 255]),
+            Coloring::Decomposition => Rgb([0, 0, 0]),
+        }
+    }
+
+    /// Position on the gradient, for pixels which color comes straight from it.
+    /// Viewer shifts it, when cycling the palette.
+    pub fn position(&self, pixel: &PixelResult) -> Option<f64> {
+        if pixel.iteration == self.context.poi.limit {
+            return None;
+        }
+
+        match self.context.coloring {
+            Coloring::Rainbow => Some(pixel.iteration as f64),
+            Coloring::Histogram => {
+                Some(self.cumulative[pixel.iteration as usize] * FRACTION_SPREAD * self.turn())
+            }
+            Coloring::Stripe(_) | Coloring::TriangleInequality => {
+                Some(pixel.average * FRACTION_SPREAD * self.turn())
+            }
+            Coloring::ExternalAngle => {
+                let (x, y) = pixel.escape;
+
+                Some(y.atan2(x).rem_euclid(TAU) / TAU * self.turn())
+            }
+            Coloring::Distance(_)
+            | Coloring::Lighting { .. }
+            | Coloring::Trap { .. }
+            | Coloring::Decomposition => None,
+        }
+    }
+
+    /// One repetition of the gradient, rainbow has a hue per degree
+    fn turn(&self) -> f64 {
+        self.context
+            .palette
+            .as_deref()
+            .map_or(360.0, Palette::length)
+    }
+
+    fn gradient(&self, position: f64) -> Rgb<u8> {
+        gradient(self.context.palette.as_deref(), position)
+    }
+}
+
+/// Color of the palette, or of the rainbow if there is none. Position is in iterations.
+pub fn gradient(palette: Option<&Palette>, position: f64) -> Rgb<u8> {
+    match palette {
+        Some(palette) => palette.color(position),
+        // Position shifted by cycling may be negative
+        None => color_rainbow(position.rem_euclid(360.0) as u32, u32::MAX),
+    }
+}
+
+fn dim(color: Rgb<u8>, factor: f64) -> Rgb<u8> {
+    let [r, g, b] = color.0;
+    let dim = |channel: u8| (channel as f64 * factor) as u8;
+
+    Rgb([dim(r), dim(g), dim(b)])
+}
+
+/// Black at 0, white from 1 on. Root makes the falloff sharp, lines are crisp.
+fn shade(distance: f64) -> Rgb<u8> {
+    let shade = (distance.min(1.0).sqrt().sqrt() * 255.0) as u8;
+
+    Rgb([shade, shade, shade])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fractal_exotic::{MandelbrotAvx2, MandelbrotAvx512},
+        fractals::{
+            interior_estimate, Area, FractalFunction, Julia, Mandelbrot, PoI, DISTANCE_BAILOUT,
+        },
+        palette::{Interpolation, Palette},
+        quadruple::Quad,
+        supersampling::Sampling,
+    };
+    use std::{marker::PhantomData, sync::Arc};
+
+    /// Single row, 1 pixel is 0.001 wide, x is from -2.2 to -2.0, and the row is at y
+    fn context(coloring: Coloring, y: f64) -> Context<f64> {
+        Context {
+            img_width: 200,
+            img_height: 1,
+            pinhole_step: 1.0,
+            sampling: Sampling::Single,
+            coloring,
+            interior: Interior::Black,
+            palette: None,
+            // Row is at the top of the view, half of the pinhole above the origin
+            poi: PoI {
+                origin_x: -2.1,
+                origin_y: y + 0.1,
+                pinhole_size: 0.2,
+                limit: 1000,
+            },
+        }
+    }
+
+    /// Scalar Mandelbrot, and the SIMD ones this CPU has
+    fn kernels() -> Vec<&'static dyn FractalFunction<f64>> {
+        let mut kernels: Vec<&dyn FractalFunction<f64>> = vec![&Mandelbrot(PhantomData)];
+        if is_x86_feature_detected!("avx2") {
+            kernels.push(&MandelbrotAvx2);
+        }
+        if is_x86_feature_detected!("avx512f") {
+            kernels.push(&MandelbrotAvx512);
+        }
+
+        kernels
+    }
+
+    /// Colors with no frame behind, enough for colorings which do not look at it
+    fn color(context: &Context<f64>, pixel: &PixelResult) -> Rgb<u8> {
+        Colorizer::new(context, &[]).color(pixel)
+    }
+
+    fn draw(context: &Context<f64>, fractal: &dyn FractalFunction<f64>) -> Vec<PixelResult> {
+        let area = Area {
+            x: 0,
+            y: 0,
+            width: context.img_width,
+            height: 1,
+            step: 1,
+        };
+        let mut pixels = vec![PixelResult::default(); area.width as usize];
+        fractal.draw(context, &area, &mut pixels);
+
+        pixels
+    }
+
+    #[test]
+    fn distance_is_estimated_only_when_needed() {
+        let pixels = draw(&context(Coloring::Rainbow, 0.0), &Mandelbrot(PhantomData));
+        assert!(pixels.iter().all(|pixel| pixel.distance == 0.0));
+
+        // Real axis, left of the tip of the set at -2
+        let context = context(Coloring::Distance(1.0), 0.0);
+        let pixels = draw(&context, &Mandelbrot(PhantomData));
+
+        for (x, pixel) in pixels.iter().enumerate() {
+            let distance = 0.2 - 0.001 * x as f64;
+
+            // True distance is between a quarter of the estimate and the estimate
+            assert!(pixel.iteration < context.poi.limit);
+            assert!(pixel.distance >= distance, "{} {:?}", distance, pixel);
+            assert!(pixel.distance <= distance * 4.0, "{} {:?}", distance, pixel);
+        }
+    }
+
+    #[test]
+    fn simd_estimates_the_same_distance() {
+        let context = context(Coloring::Distance(1.0), 0.0);
+        let expected = draw(&context, &Mandelbrot(PhantomData));
+
+        for kernel in kernels() {
+            for (pixel, expected) in draw(&context, kernel).iter().zip(expected.iter()) {
+                assert!((pixel.distance - expected.distance).abs() <= 1e-9 * expected.distance);
+            }
+        }
+    }
+
+    #[test]
+    fn filament_thinner_than_pixel_is_dark() {
+        // Tip of the set is at -2, the antenna goes to the right of it, on the real axis.
+        // Row is a fraction of a pixel above it, but no pixel is at the antenna.
+        let mut context = context(Coloring::Distance(1.0), 0.00002);
+        context.poi.origin_x = -1.8;
+        let pixels = draw(&context, &Mandelbrot(PhantomData));
+
+        let escaped: Vec<_> = pixels
+            .iter()
+            .filter(|pixel| pixel.iteration < context.poi.limit)
+            .collect();
+        assert!(escaped.len() > 100);
+
+        for pixel in escaped {
+            assert!(color(&context, pixel).0[0] < 192, "{:?}", pixel);
+        }
+
+        // Away from the set, pixels are white
+        context.poi.origin_x = -2.1;
+        let pixels = draw(&context, &Mandelbrot(PhantomData));
+        assert_eq!(color(&context, &pixels[0]), Rgb([255, 255, 255]));
+    }
+
+    #[test]
+    fn interior_is_estimated_from_the_cycle() {
+        // Nucleus of the main cardioid, z = 0 is the fixed point, boundary is 0.25 away
+        assert_eq!(interior_estimate(0.0, 0.0, 0.0, 0.0, 1), (1, 0.0, 0.5));
+        // Multiple of the period is reduced
+        assert_eq!(interior_estimate(0.0, 0.0, 0.0, 0.0, 4), (1, 0.0, 0.5));
+
+        // Real axis through the period-2 bulb, disk of radius 0.25 around -1,
+        // multiplier of its cycle is 4 * (c + 1)
+        let mut context = context(Coloring::Rainbow, 0.0);
+        context.interior = Interior::Multiplier;
+        context.poi.origin_x = -1.0;
+        context.poi.origin_y = 0.3;
+        context.poi.pinhole_size = 0.6;
+
+        let expected = draw(&context, &Mandelbrot(PhantomData));
+
+        for kernel in kernels() {
+            let pixels = draw(&context, kernel);
+            let mut found = 0;
+
+            for (x, pixel) in pixels.iter().enumerate() {
+                let c = -1.3 + 0.003 * x as f64;
+                let distance = 0.25 - (c + 1.0).abs();
+
+                if distance <= 0.0 || pixel.period == 0 {
+                    continue;
+                }
+
+                found += 1;
+                assert_eq!(pixel.period, 2);
+                assert!(
+                    (pixel.multiplier - 4.0 * (c + 1.0).abs()).abs() < 1e-6,
+                    "{:?}",
+                    pixel
+                );
+                assert!(pixel.distance >= distance * 0.999, "{} {:?}", c, pixel);
+                assert!(pixel.distance <= distance * 4.0, "{} {:?}", c, pixel);
+
+                let expected = expected[x];
+                assert!((pixel.distance - expected.distance).abs() < 1e-6 * expected.distance);
+            }
+
+            // Cycles close to the boundary converge too slow to be found
+            assert!(found > 140, "{}", found);
+        }
+    }
+
+    #[test]
+    fn interior_is_shaded() {
+        let mut context = context(Coloring::Rainbow, 0.0);
+        context.poi.origin_x = -1.0;
+        context.poi.origin_y = 0.3;
+        context.poi.pinhole_size = 0.6;
+
+        // Nucleus at -1 is in the pixel 100
+        let pixels = draw(&context, &Mandelbrot(PhantomData));
+        assert_eq!(color(&context, &pixels[100]), Rgb([0, 0, 0]));
+        assert_eq!(pixels[100].period, 0, "cardioid and bulb are not iterated");
+
+        context.interior = Interior::Multiplier;
+        let pixels = draw(&context, &Mandelbrot(PhantomData));
+        let brightness = |x: usize| color(&context, &pixels[x]).0.iter().max().copied();
+        assert_eq!(brightness(100), Some(0));
+        assert!(brightness(40) > brightness(70));
+
+        context.interior = Interior::Distance(1.0);
+        assert_eq!(color(&context, &pixels[100]), Rgb([255, 255, 255]));
+    }
+
+    /// Single pixel at c, its normal
+    fn normal<F: Floating>(fractal: &dyn FractalFunction<F>, x: f64, y: f64) -> (f64, f64) {
+        let lighting = Coloring::Lighting {
+            angle: 0.0,
+            height: 1.0,
+        };
+
+        single(fractal, lighting, x, y).normal
+    }
+
+    /// Pixel at x + yi
+    fn single<F: Floating>(
+        fractal: &dyn FractalFunction<F>,
+        coloring: Coloring,
+        x: f64,
+        y: f64,
+    ) -> PixelResult {
+        let context = Context {
+            img_width: 1,
+            img_height: 1,
+            pinhole_step: F::from(1.0),
+            sampling: Sampling::Single,
+            coloring,
+            interior: Interior::Black,
+            palette: None,
+            poi: PoI {
+                origin_x: F::from(x),
+                origin_y: F::from(y),
+                pinhole_size: F::from(0.0),
+                limit: 100,
+            },
+        };
+
+        let mut pixel = PixelResult::default();
+        let area = Area {
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+            step: 1,
+        };
+        fractal.draw(&context, &area, std::slice::from_mut(&mut pixel));
+
+        pixel
+    }
+
+    #[test]
+    fn normals_point_away_from_the_set() {
+        for kernel in kernels() {
+            assert_eq!(normal(kernel, 3.0, 0.0), (1.0, 0.0));
+            assert_eq!(normal(kernel, -3.0, 0.0), (-1.0, 0.0));
+            assert!(normal(kernel, 0.0, 3.0).1 > 0.9);
+            assert!(normal(kernel, 0.0, -3.0).1 < -0.9);
+        }
+
+        let quad = normal(&Mandelbrot::<Quad>(PhantomData), 0.3, 0.6);
+        let double = normal(&Mandelbrot::<f64>(PhantomData), 0.3, 0.6);
+        assert!((quad.0 - double.0).abs() < 1e-9 && (quad.1 - double.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn side_facing_the_light_is_brighter() {
+        let context = context(
+            Coloring::Lighting {
+                angle: 0.0,
+                height: 1.0,
+            },
+            0.0,
+        );
+
+        // Light from the right
+        let lit = |normal| {
+            let pixel = PixelResult {
+                iteration: 10,
+                normal,
+                ..Default::default()
+            };
+
+            color(&context, &pixel)
+        };
+
+        let rainbow = color_rainbow(10, context.poi.limit);
+        assert_eq!(lit((1.0, 0.0)), rainbow);
+        assert_eq!(lit((-1.0, 0.0)), Rgb([0, 0, 0]));
+        assert_eq!(lit((0.0, 1.0)), dim(rainbow, 0.5));
+    }
+
+    fn histogram(iterations: &[u32]) -> Vec<Rgb<u8>> {
+        let context = context(Coloring::Histogram, 0.0);
+        let pixels: Vec<_> = iterations
+            .iter()
+            .map(|&iteration| PixelResult {
+                iteration,
+                ..Default::default()
+            })
+            .collect();
+
+        let colorizer = Colorizer::new(&context, &pixels);
+        pixels.iter().map(|pixel| colorizer.color(pixel)).collect()
+    }
+
+    #[test]
+    fn histogram_colors_by_percentile() {
+        // Interior pixels do not count
+        let colors = histogram(&[1, 1, 5, 900, 1000, 1000]);
+
+        assert_eq!(colors[0], color_rainbow(150, u32::MAX));
+        assert_eq!(colors[2], color_rainbow(225, u32::MAX));
+        assert_eq!(colors[3], color_rainbow(300, u32::MAX));
+        assert_eq!(colors[4], Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn histogram_does_not_depend_on_iteration_spacing() {
+        let sparse: Vec<_> = (0..100).map(|i| i * 9).collect();
+        let dense: Vec<_> = (0..100).collect();
+
+        assert_eq!(histogram(&sparse), histogram(&dense));
+    }
+
+    #[test]
+    fn fractions_spread_over_the_palette_length() {
+        let mut context = context(Coloring::Histogram, 0.0);
+        let palette = Palette::new(vec![(0.0, Rgb([0, 0, 0]))], Interpolation::Rgb);
+        context.palette = Some(Arc::new(palette.with_length(60.0)));
+        let pixels: Vec<_> = [1, 1, 5, 900]
+            .iter()
+            .map(|&iteration| PixelResult {
+                iteration,
+                average: 0.5,
+                ..Default::default()
+            })
+            .collect();
+
+        let colorizer = Colorizer::new(&context, &pixels);
+        assert_eq!(colorizer.position(&pixels[0]), Some(25.0));
+        assert_eq!(colorizer.position(&pixels[2]), Some(37.5));
+        assert_eq!(colorizer.position(&pixels[3]), Some(50.0));
+
+        context.coloring = Coloring::Stripe(5.0);
+        assert_eq!(
+            Colorizer::new(&context, &[]).position(&pixels[0]),
+            Some(25.0)
+        );
+    }
+
+    #[test]
+    fn palette_replaces_the_rainbow() {
+        let mut context = context(Coloring::Rainbow, 0.0);
+        let pixel = PixelResult {
+            iteration: 90,
+            ..Default::default()
+        };
+        assert_eq!(
+            color(&context, &pixel),
+            color_rainbow(90, context.poi.limit)
+        );
+
+        let gray = Palette::new(
+            vec![(0.0, Rgb([0, 0, 0])), (0.5, Rgb([200, 200, 200]))],
+            Interpolation::Rgb,
+        );
+        context.palette = Some(Arc::new(gray));
+        assert_eq!(color(&context, &pixel), Rgb([100, 100, 100]));
+    }
+
+    #[test]
+    fn traps_measure_the_distance() {
+        let line = Trap::Line {
+            through: (0.0, 1.0),
+            angle: 45.0,
+        };
+        let circle = Trap::Circle {
+            center: (1.0, 0.0),
+            radius: 2.0,
+        };
+
+        assert_eq!(Trap::Point(1.0, 1.0).distance(4.0, 5.0), 5.0);
+        assert!((line.distance(1.0, 0.0) - 2.0f64.sqrt()).abs() < 1e-12);
+        assert_eq!(Trap::Cross(0.0, 0.0).distance(-0.5, 3.0), 0.5);
+        assert_eq!(circle.distance(1.0, 0.5), 1.5);
+        assert_eq!(circle.distance(1.0, -3.0), 1.0);
+    }
+
+    #[test]
+    fn orbit_is_trapped_the_same_in_every_kernel() {
+        // Orbit of c = 0.3 goes 0.3, 0.39, ..., and escapes
+        let trapped = Coloring::Trap {
+            trap: Trap::Point(0.39, 0.0),
+            size: 0.1,
+        };
+
+        let mandelbrot = single(&Mandelbrot::<f64>(PhantomData), trapped, 0.3, 0.0);
+        assert!(mandelbrot.iteration < 100);
+        assert!(mandelbrot.trap_distance < 1e-12);
+        assert_eq!(mandelbrot.trap_iteration, 2);
+
+        // Julia orbit starting at 0 is the Mandelbrot orbit of its c
+        let julia = single(&Julia { x: 0.3, y: 0.0 }, trapped, 0.0, 0.0);
+        assert_eq!(julia, mandelbrot);
+
+        let quad = single(&Mandelbrot::<Quad>(PhantomData), trapped, 0.3, 0.0);
+        assert_eq!(quad.iteration, mandelbrot.iteration);
+        assert_eq!(quad.trap_iteration, 2);
+
+        if is_x86_feature_detected!("avx2") {
+            assert_eq!(single(&MandelbrotAvx2, trapped, 0.3, 0.0), mandelbrot);
+        }
+        if is_x86_feature_detected!("avx512f") {
+            assert_eq!(single(&MandelbrotAvx512, trapped, 0.3, 0.0), mandelbrot);
+        }
+
+        // Nothing measured, if the coloring does not need it
+        let plain = single(&Mandelbrot::<f64>(PhantomData), Coloring::Rainbow, 0.3, 0.0);
+        assert_eq!((plain.trap_distance, plain.trap_iteration), (0.0, 0));
+    }
+
+    #[test]
+    fn pickover_stalks_fade_with_distance() {
+        let context = context(
+            Coloring::Trap {
+                trap: Trap::Cross(0.0, 0.0),
+                size: 0.1,
+            },
+            0.0,
+        );
+        let trapped = |trap_distance| PixelResult {
+            iteration: 10,
+            trap_distance,
+            trap_iteration: 5,
+            ..Default::default()
+        };
+
+        let stalk = color_rainbow(5, u32::MAX);
+        assert_eq!(color(&context, &trapped(0.0)), stalk);
+        assert_eq!(color(&context, &trapped(0.05)), dim(stalk, 0.5));
+        assert_eq!(color(&context, &trapped(0.2)), Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn averages_are_the_same_in_every_kernel() {
+        for coloring in [Coloring::Stripe(3.0), Coloring::TriangleInequality] {
+            let mandelbrot = single(&Mandelbrot::<f64>(PhantomData), coloring, 0.5, 0.5);
+            assert!(mandelbrot.iteration < 100);
+            assert!(mandelbrot.average > 0.0 && mandelbrot.average < 1.0);
+
+            let julia = single(&Julia { x: 0.5, y: 0.5 }, coloring, 0.0, 0.0);
+            assert_eq!(julia, mandelbrot);
+
+            if is_x86_feature_detected!("avx2") {
+                assert_eq!(single(&MandelbrotAvx2, coloring, 0.5, 0.5), mandelbrot);
+            }
+            if is_x86_feature_detected!("avx512f") {
+                assert_eq!(single(&MandelbrotAvx512, coloring, 0.5, 0.5), mandelbrot);
+            }
+        }
+
+        let plain = single(&Mandelbrot::<f64>(PhantomData), Coloring::Rainbow, 0.5, 0.5);
+        assert_eq!(plain.average, 0.0);
+    }
+
+    #[test]
+    fn averages_are_smooth_across_iteration_counts() {
+        for coloring in [Coloring::Stripe(3.0), Coloring::TriangleInequality] {
+            // Row from 0.5 to 0.7, at 0.3, escapes after fewer and fewer iterations
+            let pixels: Vec<_> = (0..200)
+                .map(|i| {
+                    let x = 0.5 + i as f64 * 0.001;
+                    single(&Mandelbrot::<f64>(PhantomData), coloring, x, 0.3)
+                })
+                .collect();
+
+            assert_ne!(pixels[0].iteration, pixels[199].iteration);
+            for pair in pixels.windows(2) {
+                assert!((pair[0].average - pair[1].average).abs() < 0.01);
+            }
+        }
+    }
+
+    #[test]
+    fn escape_point_is_the_same_in_every_kernel() {
+        let mandelbrot = single(
+            &Mandelbrot::<f64>(PhantomData),
+            Coloring::ExternalAngle,
+            0.5,
+            0.5,
+        );
+        let (x, y) = mandelbrot.escape;
+        assert!(mandelbrot.iteration < 100);
+        assert!(x * x + y * y >= DISTANCE_BAILOUT);
+
+        let julia = single(&Julia { x: 0.5, y: 0.5 }, Coloring::ExternalAngle, 0.0, 0.0);
+        assert_eq!(julia, mandelbrot);
+
+        let quad = single(
+            &Mandelbrot::<Quad>(PhantomData),
+            Coloring::ExternalAngle,
+            0.5,
+            0.5,
+        );
+        assert_eq!(quad.iteration, mandelbrot.iteration);
+        assert!((quad.escape.1 / y - 1.0).abs() < 1e-9);
+
+        // Every kernel escapes at the same point, SIMD ones count iterations differently
+        for kernel in kernels() {
+            assert_eq!(
+                single(kernel, Coloring::ExternalAngle, 0.5, 0.5).escape,
+                (x, y)
+            );
+        }
+
+        let plain = single(&Mandelbrot::<f64>(PhantomData), Coloring::Rainbow, 0.5, 0.5);
+        assert_eq!(plain.escape, (0.0, 0.0));
+    }
+
+    #[test]
+    fn decomposition_and_angle_come_from_the_escape_point() {
+        let escaped = |x, y| PixelResult {
+            iteration: 10,
+            escape: (x, y),
+            ..Default::default()
+        };
+
+        let context = context(Coloring::Decomposition, 0.0);
+        assert_eq!(color(&context, &escaped(-3.0, 0.5)), Rgb([255, 255, 255]));
+        assert_eq!(color(&context, &escaped(3.0, -0.5)), Rgb([0, 0, 0]));
+
+        let mut context = super::tests::context(Coloring::ExternalAngle, 0.0);
+        let position = |context: &Context<f64>, x, y| {
+            Colorizer::new(context, &[])
+                .position(&escaped(x, y))
+                .unwrap()
+        };
+        assert!((position(&context, 0.0, 2.0) - 90.0).abs() < 1e-9);
+        assert!((position(&context, 0.0, -2.0) - 270.0).abs() < 1e-9);
+
+        let palette = Palette::new(vec![(0.0, Rgb([0, 0, 0]))], Interpolation::Rgb);
+        context.palette = Some(Arc::new(palette.with_length(64.0)));
+        assert!((position(&context, -2.0, 0.0) - 32.0).abs() < 1e-9);
+    }
+}
@@ -1,9 +1,14 @@
 use std::{
-    sync::mpsc::{channel, sync_channel},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{channel, sync_channel},
+        Arc,
+    },
     thread,
     time::Instant,
 };
 
+use image::Rgb;
 use rayon::{
     iter::{IndexedParallelIterator, ParallelIterator},
     slice::ParallelSliceMut,
@@ -11,9 +16,12 @@ use rayon::{
 
 // Thanks to exact picks, there are no circular references!!
 use crate::{
+    coloring::Colorizer,
     fractal_builder::Context,
-    fractals::{Floating, FractalFunction},
-    pipe::Pipe,
+    fractals::{Area, Floating, FractalFunction, PixelResult, Statistics},
+    mariani_silver,
+    pipe::{pack, Frame, Pipe},
+    supersampling::{self, Sampling},
 };
 
 #[derive(Debug)]
@@ -27,6 +35,12 @@ pub enum Command {
     ChangeOrigin(f64, f64),
     SetPOI(u32),
     GetState,
+    /// Keeps zooming in, frame after frame, by the pinhole step
+    StartAutoZoom,
+    StopAutoZoom,
+    /// Exponent of z goes up or down by a step, for fractals which have one
+    MorePower,
+    LessPower,
 }
 
 #[derive(Debug)]
@@ -37,9 +51,32 @@ pub enum FineDirection {
     Right,
 }
 
+/// What the render thread is up to
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    /// Frame is up to date, waiting for a command
+    Idle,
+    /// View changed, frame has to be rendered once
+    Render,
+    /// Rendering frame after frame, zooming in between, until stopped
+    AutoZoom,
+}
+
+/// Fine tuning moves the view by that part of it, rounded to whole pixels
+const FINE_TUNE: f64 = 0.15;
+
+/// Discrete zoom, pinhole is multiplied by that, or divided when zooming out
+const ZOOM_FACTOR: f64 = 0.5;
+
+/// Default pinhole step, pinhole is multiplied by that every frame of the auto zoom
+pub const AUTO_ZOOM_STEP: f64 = 0.95;
+
+#[allow(dead_code)]
 pub enum ExecutorKind {
     SingleThread,
     Rayon,
+    /// Rayon, but uniform rectangles are filled instead of iterated
+    MarianiSilver,
 }
 
 /// Launches threaded backend for fractal computation.
@@ -50,22 +87,56 @@ pub trait Executor<F: Floating> {
     fn execute(&self, context: Context<F>, fractal: Box<dyn FractalFunction<F>>) -> Pipe;
 }
 
+fn next_state(state: State, command: &Command) -> State {
+    match command {
+        Command::StartAutoZoom => State::AutoZoom,
+        Command::StopAutoZoom if state == State::AutoZoom => State::Idle,
+        // View stays the same
+        Command::StopAutoZoom | Command::GetState => state,
+        // Auto zoom goes on, from the new view
+        _ if state == State::AutoZoom => state,
+        _ => State::Render,
+    }
+}
+
+/// How many pixels the view moves in x and y, when fine tuning
+fn fine_tune_shift<F: Floating>(direction: &FineDirection, context: &Context<F>) -> (i32, i32) {
+    let x = (context.img_width as f64 * FINE_TUNE).round() as i32;
+    let y = (context.img_height as f64 * FINE_TUNE).round() as i32;
+
+    match direction {
+        FineDirection::Up => (0, y),
+        FineDirection::Down => (0, -y),
+        FineDirection::Left => (-x, 0),
+        FineDirection::Right => (x, 0),
+    }
+}
+
+/// Whole pixel shift of the view, done by the command.
+/// None if the view changes in any other way, and has to be drawn again.
+fn translation<F: Floating>(command: &Command, context: &Context<F>) -> Option<(i32, i32)> {
+    match command {
+        Command::FineTune(direction) => Some(fine_tune_shift(direction, context)),
+        Command::GetState | Command::StartAutoZoom | Command::StopAutoZoom => Some((0, 0)),
+        _ => None,
+    }
+}
+
 fn handle_command<F: Floating>(command: Command, context: &mut Context<F>) {
     match command {
-        Command::ZoomOut => context.pinhole_step += F::from(0.1),
-        Command::ZoomIn => context.pinhole_step -= F::from(0.1),
+        Command::ZoomOut => context.poi.pinhole_size *= F::from(1.0 / ZOOM_FACTOR),
+        Command::ZoomIn => context.poi.pinhole_size *= F::from(ZOOM_FACTOR),
         Command::LessIterations => {
             context.poi.limit -= if context.poi.limit <= 200 { 0 } else { 200 }
         }
         Command::MoreIterations => context.poi.limit += 200,
         Command::FineTune(dir) => {
-            let tune = context.poi.pinhole_size * F::from(0.15);
-            match dir {
-                FineDirection::Up => context.poi.origin_y += tune,
-                FineDirection::Down => context.poi.origin_y -= tune,
-                FineDirection::Left => context.poi.origin_x -= tune,
-                FineDirection::Right => context.poi.origin_x += tune,
-            }
+            // Whole pixels, so already drawn ones can be reused
+            let (x, y) = fine_tune_shift(&dir, context);
+            context.poi.origin_x +=
+                context.poi.pinhole_size * F::from(x as f64 / context.img_width as f64);
+            context.poi.origin_y +=
+                context.poi.pinhole_size * F::from(y as f64 / context.img_height as f64);
         }
         Command::ChangeOrigin(x, y) => {
             let pinhole_center = context.poi.pinhole_size * F::from(0.5);
@@ -85,7 +156,7 @@ fn handle_command<F: Floating>(command: Command, context: &mut Context<F>) {
                 context.poi.origin_x = F::from(0.0);
                 context.poi.origin_y = F::from(0.0);
                 context.poi.pinhole_size = F::from(4.0);
-                context.pinhole_step = F::from(1.0);
+                context.pinhole_step = F::from(AUTO_ZOOM_STEP);
                 context.poi.limit = 200;
             }
             1 => {
@@ -135,70 +206,655 @@ fn handle_command<F: Floating>(command: Command, context: &mut Context<F>) {
             // TODO: implement div sometime eventually
             // println!("Zoom: {:#?}", F::from(4.0) / context.poi.pinhole_size);
         }
+        Command::StartAutoZoom | Command::StopAutoZoom => (),
+        // Power belongs to the fractal function, not to the context
+        Command::MorePower | Command::LessPower => (),
+    }
+}
+
+/// Applies the command, keeps track of the state, and of the pan since the last frame
+fn apply<F: Floating>(
+    command: Command,
+    state: &mut State,
+    pan: &mut Option<(i32, i32)>,
+    context: &mut Context<F>,
+    fractal: &mut dyn FractalFunction<F>,
+) {
+    println!("Got command {:?}!", command);
+
+    *state = next_state(*state, &command);
+    *pan = match (*pan, translation(&command, context)) {
+        (Some((x, y)), Some((dx, dy))) => Some((x + dx, y + dy)),
+        _ => None,
+    };
+
+    match command {
+        Command::MorePower => fractal.step_power(1),
+        Command::LessPower => fractal.step_power(-1),
+        _ => (),
+    }
+
+    handle_command(command, context);
+}
+
+/// Preview resolutions, every pass draws every step-th pixel, and reuses the previous ones
+const PASSES: [u32; 4] = [8, 4, 2, 1];
+
+/// Called every time pixels are ready to be shown, at given step, together with statistics of the pass
+type Present<'a> = dyn FnMut(&[PixelResult], u32, Statistics) + 'a;
+
+/// Fills all pixels of the image, presents the outcome, once or many times.
+/// Gives up as soon as cancel is set, without presenting the unfinished image.
+type Render<F> =
+    fn(&Context<F>, &dyn FractalFunction<F>, &mut [PixelResult], &AtomicBool, &mut Present);
+
+/// Gradient position sent to the viewer, pixels it must not recolor are colored here, and packed
+fn position<F: Floating>(
+    colorizer: &Colorizer<F>,
+    pixel: &PixelResult,
+    color: impl FnOnce() -> Rgb<u8>,
+) -> f32 {
+    colorizer
+        .position(pixel)
+        .map_or_else(|| pack(color()), |position| position as f32)
+}
+
+/// Spawns the thread rendering frames when the view changes, and handling commands in between
+fn spawn<F: Floating>(
+    context: Context<F>,
+    fractal: Box<dyn FractalFunction<F>>,
+    render: Render<F>,
+) -> Pipe {
+    let (img_send, img_rcv) = sync_channel(1);
+
+    let (cmd_send, cmd_rcv) = channel();
+
+    let pipe = Pipe {
+        cmd_send: cmd_send,
+        img_rcv: img_rcv,
+    };
+
+    // Render thread is busy, someone else has to notice a new command, and cancel the frame
+    let (queue_send, queue_rcv) = channel();
+    let cancel = Arc::new(AtomicBool::new(false));
+    let cancel_render = cancel.clone();
+    // Commands handed over so far, render thread compares it with the ones it took
+    let queued = Arc::new(AtomicUsize::new(0));
+    let queued_render = queued.clone();
+
+    thread::spawn(move || {
+        for command in cmd_rcv {
+            // Counted and flagged before it is queued, so the render thread never sees
+            // the command without the flag, and knows when the flag it clears is not its own
+            queued.fetch_add(1, Ordering::SeqCst);
+            cancel.store(true, Ordering::SeqCst);
+
+            if queue_send.send(command).is_err() {
+                break;
+            }
+        }
+    });
+
+    thread::spawn(move || {
+        let cancel = cancel_render;
+        let queued = queued_render;
+        let mut taken = 0;
+        // Changing mutability here
+        // TODO: is it better way to do it???
+        let mut context = context;
+        let mut fractal = fractal;
+
+        let pixels_count = (context.img_width * context.img_height) as usize;
+
+        let mut pixels = vec![PixelResult::default(); pixels_count];
+
+        let mut state = State::Render;
+
+        // Pixels of the last frame, moved by that, are still valid. None if there is nothing to reuse.
+        let mut pan = None;
+
+        loop {
+            if state == State::Idle {
+                match queue_rcv.recv() {
+                    Ok(command) => {
+                        taken += 1;
+                        apply(command, &mut state, &mut pan, &mut context, &mut *fractal)
+                    }
+                    // Nobody is listening anymore
+                    Err(_) => return,
+                }
+            }
+
+            let start = Instant::now();
+
+            // Apply everything what piled up, rendering intermediate views is pointless
+            for command in queue_rcv.try_iter() {
+                taken += 1;
+                apply(command, &mut state, &mut pan, &mut context, &mut *fractal);
+            }
+
+            // Flag is cleared after the queue is drained. A command counted, but queued too late
+            // for the drain, raises it again here, so this frame is dropped and the command taken
+            // right away. Any command counted after the check raises the flag on its own.
+            cancel.store(false, Ordering::SeqCst);
+            if queued.load(Ordering::SeqCst) != taken {
+                cancel.store(true, Ordering::SeqCst);
+            }
+
+            if state == State::Idle {
+                continue;
+            }
+
+            let mut present = |pixels: &[PixelResult], step: u32, stats: Statistics| {
+                let width = context.img_width as usize;
+                let drawn = pixels
+                    .chunks(width)
+                    .step_by(step as usize)
+                    .flat_map(|row| row.iter().step_by(step as usize));
+                let colorizer = Colorizer::new(&context, drawn);
+
+                // Pixel not drawn yet takes the value of the drawn one, top left to it
+                let drawn = |x: u32, y: u32| {
+                    &pixels[((y - y % step) * context.img_width + x - x % step) as usize]
+                };
+                let positions = (0..context.img_height)
+                    .flat_map(|y| (0..context.img_width).map(move |x| (x, y)))
+                    .map(|(x, y)| drawn(x, y))
+                    .map(|pixel| position(&colorizer, pixel, || colorizer.color(pixel)))
+                    .collect();
+
+                println!(
+                    "render step {} took {}, {:?}",
+                    step,
+                    start.elapsed().as_millis(),
+                    stats
+                );
+
+                img_send
+                    .send(Frame {
+                        width: context.img_width,
+                        height: context.img_height,
+                        positions,
+                        palette: context.palette.clone(),
+                    })
+                    .unwrap();
+            };
+
+            let width = context.img_width as i32;
+            let height = context.img_height as i32;
+
+            match pan.filter(|(x, y)| x.abs() < width && y.abs() < height) {
+                Some(shift) => {
+                    let stats = render_panned(&context, &*fractal, &mut pixels, shift, &cancel);
+
+                    if !cancel.load(Ordering::Relaxed) {
+                        present(&pixels, 1, stats);
+                    }
+                }
+                None => render(&context, &*fractal, &mut pixels, &cancel, &mut present),
+            }
+
+            // Anti-aliased image comes the last, it takes the longest
+            if context.sampling != Sampling::Single && !cancel.load(Ordering::Relaxed) {
+                let colorizer = Colorizer::new(&context, &pixels);
+                let whole = Area {
+                    x: 0,
+                    y: 0,
+                    width: context.img_width,
+                    height: context.img_height,
+                    step: 1,
+                };
+                let (colors, stats) = supersampling::colorize(
+                    &context, &colorizer, &*fractal, &pixels, &whole, &cancel,
+                );
+
+                if !cancel.load(Ordering::Relaxed) {
+                    println!(
+                        "{:?} took {}, {:?}",
+                        context.sampling,
+                        start.elapsed().as_millis(),
+                        stats
+                    );
+
+                    // Cycled palette has no samples to average, it is one sample per pixel again
+                    let positions = pixels
+                        .iter()
+                        .zip(colors)
+                        .map(|(pixel, color)| position(&colorizer, pixel, || color))
+                        .collect();
+
+                    img_send
+                        .send(Frame {
+                            width: context.img_width,
+                            height: context.img_height,
+                            positions,
+                            palette: context.palette.clone(),
+                        })
+                        .unwrap();
+                }
+            }
+
+            // Unfinished frame, there are new commands waiting
+            if cancel.load(Ordering::Relaxed) {
+                pan = None;
+                continue;
+            }
+
+            pan = Some((0, 0));
+
+            match state {
+                State::Render => state = State::Idle,
+                State::AutoZoom => {
+                    context.poi.pinhole_size *= context.pinhole_step;
+                    pan = None;
+                }
+                State::Idle => (),
+            }
+        }
+    });
+
+    pipe
+}
+
+/// Draws the image in passes, from the coarse to the full resolution, presenting each one.
+/// Pixels drawn in a pass are not drawn again in the next ones.
+fn render_progressive<F: Floating>(
+    context: &Context<F>,
+    fractal: &dyn FractalFunction<F>,
+    pixels: &mut [PixelResult],
+    cancel: &AtomicBool,
+    present: &mut Present,
+) {
+    for (pass, &step) in PASSES.iter().enumerate() {
+        let stats = pixels
+            .par_chunks_mut((step * context.img_width) as usize)
+            .enumerate()
+            .map(|(id, chunk)| {
+                if cancel.load(Ordering::Relaxed) {
+                    return Statistics::default();
+                }
+
+                let y = id as u32 * step;
+
+                // In rows drawn by the previous pass, only every other pixel is missing
+                let (x, x_step) = if pass > 0 && y % (2 * step) == 0 {
+                    (step, 2 * step)
+                } else {
+                    (0, step)
+                };
+
+                if x >= context.img_width {
+                    return Statistics::default();
+                }
+
+                let area = Area {
+                    x,
+                    y,
+                    width: (context.img_width - x + x_step - 1) / x_step,
+                    height: 1,
+                    step: x_step,
+                };
+
+                let mut row = vec![PixelResult::default(); area.width as usize];
+                let stats = fractal.draw(context, &area, &mut row);
+
+                for (i, pixel) in row.into_iter().enumerate() {
+                    chunk[(x + i as u32 * x_step) as usize] = pixel;
+                }
+
+                stats
+            })
+            .reduce(Statistics::default, |a, b| a + b);
+
+        if cancel.load(Ordering::Relaxed) {
+            return;
+        }
+
+        present(pixels, step, stats);
     }
 }
 
+/// View moved by the whole pixels, those still visible are moved,
+/// only the newly exposed strips are drawn.
+fn render_panned<F: Floating>(
+    context: &Context<F>,
+    fractal: &dyn FractalFunction<F>,
+    pixels: &mut [PixelResult],
+    (dx, dy): (i32, i32),
+    cancel: &AtomicBool,
+) -> Statistics {
+    let width = context.img_width as i32;
+    let height = context.img_height as i32;
+    let previous = pixels.to_vec();
+
+    // Columns which were not visible before
+    let (strip_x, strip_width) = if dx > 0 { (width - dx, dx) } else { (0, -dx) };
+
+    pixels
+        .par_chunks_mut(context.img_width as usize)
+        .enumerate()
+        .map(|(y, row)| {
+            if cancel.load(Ordering::Relaxed) {
+                return Statistics::default();
+            }
+
+            let mut area = Area {
+                x: 0,
+                y: y as u32,
+                width: context.img_width,
+                height: 1,
+                step: 1,
+            };
+
+            let previous_y = y as i32 + dy;
+            if previous_y < 0 || previous_y >= height {
+                return fractal.draw(context, &area, row);
+            }
+
+            let previous_row = &previous[(previous_y * width) as usize..][..width as usize];
+            for x in 0..width {
+                let previous_x = x + dx;
+                if previous_x >= 0 && previous_x < width {
+                    row[x as usize] = previous_row[previous_x as usize];
+                }
+            }
+
+            if strip_width == 0 {
+                return Statistics::default();
+            }
+
+            area.x = strip_x as u32;
+            area.width = strip_width as u32;
+
+            fractal.draw(
+                context,
+                &area,
+                &mut row[strip_x as usize..(strip_x + strip_width) as usize],
+            )
+        })
+        .reduce(Statistics::default, |a, b| a + b)
+}
+
+fn render_subdivided<F: Floating>(
+    context: &Context<F>,
+    fractal: &dyn FractalFunction<F>,
+    pixels: &mut [PixelResult],
+    cancel: &AtomicBool,
+    present: &mut Present,
+) {
+    let stats = mariani_silver::render(context, fractal, pixels, cancel);
+
+    if cancel.load(Ordering::Relaxed) {
+        return;
+    }
+
+    present(pixels, 1, stats);
+}
+
 pub struct Rayon;
 
 impl<F: Floating> Executor<F> for Rayon {
     fn execute(&self, context: Context<F>, fractal: Box<dyn FractalFunction<F>>) -> Pipe {
-        let (img_send, img_rcv) = sync_channel(1);
+        spawn(context, fractal, render_progressive)
+    }
+}
+
+pub struct MarianiSilver;
+
+impl<F: Floating> Executor<F> for MarianiSilver {
+    fn execute(&self, context: Context<F>, fractal: Box<dyn FractalFunction<F>>) -> Pipe {
+        spawn(context, fractal, render_subdivided)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        coloring::{Coloring, Interior},
+        fractals::{Mandelbrot, PoI},
+        supersampling::Sampling,
+    };
+    use std::{marker::PhantomData, sync::atomic::AtomicU32};
 
-        let (cmd_send, cmd_rcv) = channel();
+    #[test]
+    fn state_changes_on_commands() {
+        use State::*;
 
-        let pipe = Pipe {
-            cmd_send: cmd_send,
-            img_rcv: img_rcv,
+        assert_eq!(next_state(Idle, &Command::ZoomIn), Render);
+        assert_eq!(next_state(Idle, &Command::GetState), Idle);
+        assert_eq!(next_state(Idle, &Command::StopAutoZoom), Idle);
+        assert_eq!(next_state(Idle, &Command::StartAutoZoom), AutoZoom);
+        assert_eq!(next_state(Render, &Command::GetState), Render);
+        assert_eq!(next_state(AutoZoom, &Command::MoreIterations), AutoZoom);
+        assert_eq!(next_state(AutoZoom, &Command::StopAutoZoom), Idle);
+    }
+
+    #[test]
+    fn zoom_is_discrete() {
+        let mut context = Context {
+            img_width: 64,
+            img_height: 64,
+            pinhole_step: 0.9,
+            sampling: Sampling::Single,
+            coloring: Coloring::Rainbow,
+            interior: Interior::Black,
+            palette: None,
+            poi: PoI {
+                origin_x: -0.5,
+                origin_y: 0.0,
+                pinhole_size: 4.0,
+                limit: 300,
+            },
         };
 
-        thread::spawn(move || {
-            // Changing mutability here
-            // TODO: is it better way to do it???
-            let mut context = context;
-            let num_threads = num_cpus::get();
+        handle_command(Command::ZoomIn, &mut context);
+        assert_eq!(context.poi.pinhole_size, 2.0);
+        handle_command(Command::ZoomIn, &mut context);
+        assert_eq!(context.poi.pinhole_size, 1.0);
+        handle_command(Command::ZoomOut, &mut context);
+        assert_eq!(context.poi.pinhole_size, 2.0);
 
-            let pixels_count = (context.img_width * context.img_height) as usize;
+        // Auto zoom speed is not touched
+        assert_eq!(context.pinhole_step, 0.9);
+    }
 
-            let mut pixels = vec![image::Rgb::from([0u8, 0, 0]); pixels_count];
+    #[test]
+    fn progressive_passes_match_full_draw() {
+        // Not multiple of any step
+        let context = Context {
+            img_width: 101,
+            img_height: 75,
+            pinhole_step: 1.0,
+            sampling: Sampling::Single,
+            coloring: Coloring::Rainbow,
+            interior: Interior::Black,
+            palette: None,
+            poi: PoI {
+                origin_x: -0.5,
+                origin_y: 0.0,
+                pinhole_size: 3.0,
+                limit: 300,
+            },
+        };
+        let fractal = Mandelbrot::<f64>(PhantomData);
 
-            let chunk_size = pixels_count / num_threads;
+        let whole = Area {
+            x: 0,
+            y: 0,
+            width: context.img_width,
+            height: context.img_height,
+            step: 1,
+        };
+        let mut expected = vec![PixelResult::default(); (whole.width * whole.height) as usize];
+        let expected_stats = fractal.draw(&context, &whole, &mut expected);
 
-            loop {
-                let start = Instant::now();
-                match cmd_rcv.try_recv() {
-                    Ok(command) => {
-                        println!("Got command {:?}!", command);
-                        handle_command(command, &mut context);
+        // Iteration never reaches that, so the pixel was not drawn
+        let not_drawn = PixelResult {
+            iteration: u32::MAX,
+            ..Default::default()
+        };
+        let mut pixels = vec![not_drawn; expected.len()];
+        let mut steps = vec![];
+
+        render_progressive(
+            &context,
+            &fractal,
+            &mut pixels,
+            &AtomicBool::new(false),
+            &mut |pixels, step, stats| {
+                // Every step-th pixel is there, and has the final value
+                for y in (0..context.img_height).step_by(step as usize) {
+                    for x in (0..context.img_width).step_by(step as usize) {
+                        let index = (y * context.img_width + x) as usize;
+                        assert_eq!(pixels[index], expected[index], "{} {} {}", x, y, step);
                     }
-                    Err(_) => (),
                 }
 
-                let _: Vec<_> = pixels
-                    .par_chunks_mut(chunk_size)
-                    .enumerate()
-                    .map(|(id, chunk)| {
-                        fractal.draw(
-                            &context,
-                            id as u32,
-                            context.img_height / num_threads as u32,
-                            chunk,
-                        );
-                    })
-                    .collect();
+                steps.push((step, stats));
+            },
+        );
 
-                let image =
-                    image::ImageBuffer::from_fn(context.img_width, context.img_height, |x, y| {
-                        pixels[(y * context.img_width + x) as usize]
-                    });
+        assert_eq!(pixels, expected);
+        assert_eq!(
+            steps.iter().map(|(step, _)| *step).collect::<Vec<_>>(),
+            PASSES
+        );
 
-                println!("render took {}", start.elapsed().as_millis());
+        // Nothing is drawn twice
+        let stats = steps
+            .iter()
+            .fold(Statistics::default(), |sum, (_, stats)| sum + *stats);
+        assert_eq!(stats.interior_shortcut, expected_stats.interior_shortcut);
+        assert_eq!(stats.periodic, expected_stats.periodic);
+    }
 
-                img_send.send(image).unwrap();
+    /// Counts how many pixels were drawn
+    struct Counting(Mandelbrot<f64>, AtomicU32);
 
-                context.poi.pinhole_size *= context.pinhole_step;
-            }
-        });
+    impl FractalFunction<f64> for Counting {
+        fn draw(
+            &self,
+            context: &Context<f64>,
+            area: &Area,
+            pixels: &mut [PixelResult],
+        ) -> Statistics {
+            self.1
+                .fetch_add(area.width * area.height, Ordering::Relaxed);
+            self.0.draw(context, area, pixels)
+        }
+    }
+
+    #[test]
+    fn pan_draws_only_exposed_pixels() {
+        // Off the real axis, antenna of the set is too thin to survive a rounding error
+        let mut context = Context {
+            img_width: 100,
+            img_height: 80,
+            pinhole_step: 1.0,
+            sampling: Sampling::Single,
+            coloring: Coloring::Rainbow,
+            interior: Interior::Black,
+            palette: None,
+            poi: PoI {
+                origin_x: -0.5,
+                origin_y: 0.1,
+                pinhole_size: 3.0,
+                limit: 300,
+            },
+        };
+        let whole = Area {
+            x: 0,
+            y: 0,
+            width: context.img_width,
+            height: context.img_height,
+            step: 1,
+        };
+        let fractal = Counting(Mandelbrot(PhantomData), AtomicU32::new(0));
+        let mut pixels = vec![PixelResult::default(); 100 * 80];
+        fractal.draw(&context, &whole, &mut pixels);
+
+        for (direction, exposed) in [
+            (FineDirection::Right, 15 * 80),
+            (FineDirection::Up, 12 * 100),
+            (FineDirection::Left, 15 * 80),
+            (FineDirection::Down, 12 * 100),
+        ] {
+            let shift = fine_tune_shift(&direction, &context);
+            handle_command(Command::FineTune(direction), &mut context);
+
+            fractal.1.store(0, Ordering::Relaxed);
+            render_panned(
+                &context,
+                &fractal,
+                &mut pixels,
+                shift,
+                &AtomicBool::new(false),
+            );
+            assert_eq!(fractal.1.load(Ordering::Relaxed), exposed);
+
+            let mut expected = vec![PixelResult::default(); pixels.len()];
+            fractal.draw(&context, &whole, &mut expected);
+
+            // Moved origin is not exactly the same as moved pixels,
+            // pixels on the edge of escaping can differ
+            let diff = pixels
+                .iter()
+                .zip(expected.iter())
+                .filter(|(a, b)| a != b)
+                .count();
+            assert!(diff < pixels.len() / 1000, "{}", diff);
+        }
+    }
+
+    #[test]
+    fn cancelled_render_is_not_presented() {
+        let context = Context {
+            img_width: 64,
+            img_height: 64,
+            pinhole_step: 1.0,
+            sampling: Sampling::Single,
+            coloring: Coloring::Rainbow,
+            interior: Interior::Black,
+            palette: None,
+            poi: PoI {
+                origin_x: -0.5,
+                origin_y: 0.0,
+                pinhole_size: 3.0,
+                limit: 300,
+            },
+        };
+        let fractal = Mandelbrot::<f64>(PhantomData);
+        let mut pixels = vec![PixelResult::default(); 64 * 64];
+
+        // Command came before anything was drawn
+        let cancel = AtomicBool::new(true);
+        let mut steps = vec![];
+        render_progressive(
+            &context,
+            &fractal,
+            &mut pixels,
+            &cancel,
+            &mut |_, step, _| steps.push(step),
+        );
+
+        assert!(steps.is_empty());
+        assert!(pixels.iter().all(|p| *p == PixelResult::default()));
+
+        // Command came during the first pass
+        let cancel = AtomicBool::new(false);
+        let mut steps = vec![];
+        render_progressive(
+            &context,
+            &fractal,
+            &mut pixels,
+            &cancel,
+            &mut |_, step, _| {
+                steps.push(step);
+                cancel.store(true, Ordering::Relaxed);
+            },
+        );
 
-        pipe
+        assert_eq!(steps, [PASSES[0]]);
     }
 }
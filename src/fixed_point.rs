@@ -0,0 +1,552 @@
+//! Fixed point arithmetic, tailored to the Mandelbrot iteration.
+//! Points of the set stay within |z| < 2, so exponent in floating types is
+//! a wasted work. Here the number is a big integer in two's complement,
+//! with the binary point at fixed position.
+//!
+//! |z| < 2 needs a single integer bit, but x2 and y2 of such z reach 4, so one bit of
+//! headroom is kept above it, and the sign above that. Everything else in LIMBS u64 words
+//! is a fraction. Overflow saturates, like the floats go to infinity: sum = x2 + y2 of 4 or
+//! more saturates to max(), which is where from(4.0) saturates too, so the bailout is exact.
+//! The far bailout of colorings saturates to the same max(), it is no further than the near one.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, AddAssign, Mul, MulAssign, Sub, SubAssign};
+
+use crate::fractals::{Abs, Epsilon};
+
+const LIMB_WIDTH: u32 = u64::BITS;
+const INTEGER_BITS: u32 = 1;
+const HEADROOM_BITS: u32 = 1;
+// Bits above the binary point: sign, headroom and the integer part
+const TOP_BITS: u32 = 1 + HEADROOM_BITS + INTEGER_BITS;
+
+// binary64 layout, needed for conversion from f64
+const F64_BIAS: i64 = 1023;
+const F64_EXPONENT_MASK: u64 = 0x7FF;
+const F64_SIGNIFICAND_WIDTH: u32 = 52;
+const F64_SIGNIFICAND_MASK: u64 = 0xFFFFFFFFFFFFF;
+
+/// Value of the number is bits / 2^FRACTION_BITS, two's complement in little endian limbs.
+// clone + copy to be able to do: x + x etc.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct FixedPoint<const LIMBS: usize> {
+    bits: [u64; LIMBS],
+}
+
+impl<const LIMBS: usize> FixedPoint<LIMBS> {
+    pub const FRACTION_BITS: u32 = LIMBS as u32 * LIMB_WIDTH - TOP_BITS;
+
+    pub fn zero() -> Self {
+        Self { bits: [0; LIMBS] }
+    }
+
+    /// Biggest representable number, a bit less than 2^(TOP_BITS - 1)
+    pub fn max() -> Self {
+        let mut bits = [u64::MAX; LIMBS];
+        bits[LIMBS - 1] = i64::MAX as u64;
+
+        Self { bits }
+    }
+
+    /// Smallest representable number, -2^(TOP_BITS - 1)
+    pub fn min() -> Self {
+        let mut bits = [0; LIMBS];
+        bits[LIMBS - 1] = i64::MIN as u64;
+
+        Self { bits }
+    }
+
+    fn saturated(negative: bool) -> Self {
+        if negative {
+            Self::min()
+        } else {
+            Self::max()
+        }
+    }
+
+    pub fn is_negative(&self) -> bool {
+        (self.bits[LIMBS - 1] as i64) < 0
+    }
+
+    /// Two's complement: invert and add one
+    fn negate(bits: [u64; LIMBS]) -> [u64; LIMBS] {
+        let mut result = [0; LIMBS];
+        let mut carry = true;
+
+        for (r, limb) in result.iter_mut().zip(bits.iter()) {
+            let (sum, c) = (!limb).overflowing_add(carry as u64);
+            *r = sum;
+            carry = c;
+        }
+
+        result
+    }
+
+    /// Absolute value as an unsigned big integer, it does not overflow even for min()
+    fn magnitude(&self) -> [u64; LIMBS] {
+        if self.is_negative() {
+            Self::negate(self.bits)
+        } else {
+            self.bits
+        }
+    }
+
+    /// Unsigned big integer back to the signed one, saturates when it does not fit
+    fn from_magnitude(negative: bool, magnitude: [u64; LIMBS]) -> Self {
+        if (magnitude[LIMBS - 1] as i64) < 0 {
+            return Self::saturated(negative);
+        }
+
+        if negative {
+            Self {
+                bits: Self::negate(magnitude),
+            }
+        } else {
+            Self { bits: magnitude }
+        }
+    }
+}
+
+impl<const LIMBS: usize> Default for FixedPoint<LIMBS> {
+    fn default() -> Self {
+        Self::zero()
+    }
+}
+
+impl<const LIMBS: usize> From<f64> for FixedPoint<LIMBS> {
+    /// Bits below the resolution are truncated, values out of range saturate
+    fn from(a: f64) -> Self {
+        let bits = a.to_bits();
+
+        let negative = (bits >> 63) == 1;
+        let exponent = (bits >> F64_SIGNIFICAND_WIDTH) & F64_EXPONENT_MASK;
+        let significand = bits & F64_SIGNIFICAND_MASK;
+
+        assert!(
+            exponent != F64_EXPONENT_MASK || significand == 0,
+            "FixedPoint does not support NaNs"
+        );
+
+        if exponent == F64_EXPONENT_MASK {
+            return Self::saturated(negative);
+        }
+
+        // Subnormals have the same scale as the smallest normal number
+        let (exponent, significand) = if exponent == 0 {
+            (1, significand)
+        } else {
+            (exponent as i64, significand | (1 << F64_SIGNIFICAND_WIDTH))
+        };
+
+        // f64 value is significand * 2^(exponent - bias - 52), here the value is
+        // bits * 2^-FRACTION_BITS, so significand has to be shifted by the difference.
+        let shift = exponent - F64_BIAS - F64_SIGNIFICAND_WIDTH as i64 + Self::FRACTION_BITS as i64;
+
+        let mut magnitude = [0; LIMBS];
+
+        if shift < 0 {
+            if shift > -(LIMB_WIDTH as i64) {
+                magnitude[0] = significand >> -shift;
+            }
+        } else {
+            let leading_bit = (LIMB_WIDTH - 1 - significand.leading_zeros()) as i64;
+            if leading_bit + shift >= (LIMBS as u32 * LIMB_WIDTH) as i64 {
+                return Self::saturated(negative);
+            }
+
+            let limb = (shift / LIMB_WIDTH as i64) as usize;
+            let bits = (shift % LIMB_WIDTH as i64) as u32;
+
+            magnitude[limb] = significand << bits;
+            if bits > 0 && limb + 1 < LIMBS {
+                magnitude[limb + 1] = significand >> (LIMB_WIDTH - bits);
+            }
+        }
+
+        Self::from_magnitude(negative, magnitude)
+    }
+}
+
+impl<const LIMBS: usize> From<FixedPoint<LIMBS>> for f64 {
+    fn from(a: FixedPoint<LIMBS>) -> Self {
+        // Good enough for printing, exact if the number fits in the f64 significand
+        let value = a
+            .magnitude()
+            .iter()
+            .enumerate()
+            .map(|(i, &limb)| {
+                limb as f64
+                    * 2.0f64.powi(
+                        i as i32 * LIMB_WIDTH as i32 - FixedPoint::<LIMBS>::FRACTION_BITS as i32,
+                    )
+            })
+            .sum::<f64>();
+
+        if a.is_negative() {
+            -value
+        } else {
+            value
+        }
+    }
+}
+
+/// Get nice "float like" representation
+impl<const LIMBS: usize> fmt::Display for FixedPoint<LIMBS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", f64::from(*self))
+    }
+}
+
+impl<const LIMBS: usize> Add for FixedPoint<LIMBS> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        // That is where fixed point shines, no aligning, no normalization,
+        // two's complement takes care of signs
+        let mut bits = [0; LIMBS];
+        let mut carry = false;
+
+        for i in 0..LIMBS {
+            let (sum, c1) = self.bits[i].overflowing_add(rhs.bits[i]);
+            let (sum, c2) = sum.overflowing_add(carry as u64);
+            bits[i] = sum;
+            carry = c1 || c2;
+        }
+
+        let result = Self { bits };
+
+        // Overflow happens only when both have the same sign, and result has different one
+        if self.is_negative() == rhs.is_negative() && result.is_negative() != self.is_negative() {
+            return Self::saturated(self.is_negative());
+        }
+
+        result
+    }
+}
+
+impl<const LIMBS: usize> AddAssign for FixedPoint<LIMBS> {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl<const LIMBS: usize> Sub for FixedPoint<LIMBS> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        let mut bits = [0; LIMBS];
+        let mut borrow = false;
+
+        for i in 0..LIMBS {
+            let (diff, b1) = self.bits[i].overflowing_sub(rhs.bits[i]);
+            let (diff, b2) = diff.overflowing_sub(borrow as u64);
+            bits[i] = diff;
+            borrow = b1 || b2;
+        }
+
+        let result = Self { bits };
+
+        // Overflow happens only when signs differ, and result has the sign of rhs
+        if self.is_negative() != rhs.is_negative() && result.is_negative() != self.is_negative() {
+            return Self::saturated(self.is_negative());
+        }
+
+        result
+    }
+}
+
+impl<const LIMBS: usize> SubAssign for FixedPoint<LIMBS> {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+impl<const LIMBS: usize> Mul for FixedPoint<LIMBS> {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        let negative = self.is_negative() ^ rhs.is_negative();
+        let a = self.magnitude();
+        let b = rhs.magnitude();
+
+        // Schoolbook multiplication of magnitudes, u64 * u64 -> u128 gives
+        // multiply-high for free. Product has 2 * LIMBS limbs, and it has to be
+        // shifted right by FRACTION_BITS = (LIMBS - 1) * 64 + (64 - TOP_BITS).
+        // So limbs below LIMBS - 1 are not needed, except for carries,
+        // limb LIMBS - 1 gives the TOP_BITS lowest bits of the result.
+        let mut below = 0;
+        let mut upper = [0; LIMBS];
+
+        // 'carry' keeps two limbs worth of a column sum
+        let mut carry: u128 = 0;
+        for column in 0..2 * LIMBS {
+            let mut sum = carry;
+            let mut overflow: u128 = 0;
+
+            let first = column.saturating_sub(LIMBS - 1);
+            for i in first..=column.min(LIMBS - 1) {
+                let product = a[i] as u128 * b[column - i] as u128;
+                let (s, o) = sum.overflowing_add(product);
+                sum = s;
+                overflow += o as u128;
+            }
+
+            let limb = sum as u64;
+            carry = (sum >> LIMB_WIDTH) | (overflow << LIMB_WIDTH);
+
+            if column + 1 == LIMBS {
+                below = limb;
+            } else if column >= LIMBS {
+                upper[column - LIMBS] = limb;
+            }
+        }
+
+        // Anything above the top bits does not fit
+        if upper[LIMBS - 1] >> (LIMB_WIDTH - TOP_BITS) != 0 {
+            return Self::saturated(negative);
+        }
+
+        let shift = LIMB_WIDTH - TOP_BITS;
+        let mut magnitude = [0; LIMBS];
+        for i in 0..LIMBS {
+            let lower = if i == 0 { below } else { upper[i - 1] };
+            magnitude[i] = (lower >> shift) | (upper[i] << TOP_BITS);
+        }
+
+        Self::from_magnitude(negative, magnitude)
+    }
+}
+
+impl<const LIMBS: usize> MulAssign for FixedPoint<LIMBS> {
+    fn mul_assign(&mut self, rhs: Self) {
+        *self = *self * rhs;
+    }
+}
+
+impl<const LIMBS: usize> Epsilon for FixedPoint<LIMBS> {
+    /// Resolution is the same for every number, it is the last bit
+    fn epsilon() -> Self {
+        let mut bits = [0; LIMBS];
+        bits[0] = 1;
+
+        Self { bits }
+    }
+}
+
+impl<const LIMBS: usize> Abs for FixedPoint<LIMBS> {
+    /// min() has no positive counterpart, it saturates to max()
+    fn abs(self) -> Self {
+        Self::from_magnitude(false, self.magnitude())
+    }
+}
+
+impl<const LIMBS: usize> PartialOrd for FixedPoint<LIMBS> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        // Most significant limb carries the sign, the rest compares as unsigned
+        let top = (self.bits[LIMBS - 1] as i64).cmp(&(other.bits[LIMBS - 1] as i64));
+
+        Some(top.then_with(|| {
+            self.bits[..LIMBS - 1]
+                .iter()
+                .rev()
+                .cmp(other.bits[..LIMBS - 1].iter().rev())
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+
+    use super::*;
+    use crate::{
+        big_float::BigFloat,
+        coloring::{Coloring, Interior},
+        fractal_builder::Context,
+        fractal_exotic::MandelbrotRug,
+        fractals::{Area, Floating, FractalFunction, Mandelbrot, PixelResult, PoI},
+        quadruple::Quad,
+        supersampling::Sampling,
+    };
+    use std::marker::PhantomData;
+    use test::Bencher;
+
+    const VALUES: [f64; 12] = [
+        0.0,
+        1.0,
+        -1.0,
+        0.5,
+        -0.75,
+        1.5,
+        -2.0,
+        3.75,
+        0.1,
+        -0.7436438870371587,
+        0.13182590420531198,
+        -0.000000000000000038614262509059454,
+    ];
+
+    #[test]
+    fn convert_from_and_to_double_works() {
+        for a in VALUES {
+            // Only 61 bits of fraction, tiny values are truncated
+            assert!((f64::from(FixedPoint::<1>::from(a)) - a).abs() < 2.0f64.powi(-61));
+            assert_eq!(f64::from(FixedPoint::<2>::from(a)), a);
+            assert_eq!(f64::from(FixedPoint::<4>::from(a)), a);
+        }
+
+        // Out of range
+        assert_eq!(FixedPoint::<2>::from(1000.0), FixedPoint::max());
+        assert_eq!(FixedPoint::<2>::from(-1000.0), FixedPoint::min());
+        assert_eq!(FixedPoint::<2>::from(f64::INFINITY), FixedPoint::max());
+        assert_eq!(FixedPoint::<2>::from(4.0), FixedPoint::max());
+        assert_eq!(f64::from(FixedPoint::<2>::from(-4.0)), -4.0);
+
+        // Below resolution
+        assert_eq!(FixedPoint::<1>::from(1e-30), FixedPoint::zero());
+        assert_eq!(format!("{}", FixedPoint::<2>::from(-0.1)), "-0.1");
+    }
+
+    #[test]
+    fn arithmetic_works() {
+        // Dyadic values, so results are exact for both types
+        let values = [0.0, 1.0, -1.0, 0.5, -0.75, 1.5, -2.0, 3.25, 0.125, -0.0625];
+
+        for a in values {
+            for b in values {
+                let (fa, fb) = (FixedPoint::<2>::from(a), FixedPoint::<2>::from(b));
+
+                assert_eq!(fa + fb, FixedPoint::from(a + b));
+                assert_eq!(fa - fb, FixedPoint::from(a - b));
+                assert_eq!(fa * fb, FixedPoint::from(a * b));
+                assert_eq!(fa.partial_cmp(&fb), a.partial_cmp(&b));
+            }
+
+            assert_eq!(FixedPoint::<2>::from(a).abs(), FixedPoint::from(a.abs()));
+        }
+    }
+
+    #[test]
+    fn precision_works() {
+        // 2^-100 is far beyond f64 precision when added to 1, but not here
+        let one = FixedPoint::<2>::from(1.0);
+        let tiny = FixedPoint::<2>::from(2.0f64.powi(-100));
+
+        assert!(one + tiny > one);
+        assert_eq!((one + tiny) - one, tiny);
+        assert_eq!((one + tiny) * (one + tiny), one + tiny + tiny);
+        assert_eq!(tiny * tiny, FixedPoint::zero());
+
+        // Result of multiplication is truncated, that is rounded towards zero
+        let third = FixedPoint::<1>::from(1.0 / 3.0);
+        let minus_third = FixedPoint::<1>::from(-1.0 / 3.0);
+        assert_eq!(third * third, minus_third * minus_third);
+        assert_eq!(third * minus_third, FixedPoint::zero() - third * third);
+    }
+
+    #[test]
+    fn saturation_works() {
+        let big = FixedPoint::<2>::from(100.0);
+
+        assert_eq!(big + big, FixedPoint::max());
+        assert_eq!(FixedPoint::zero() - big - big, FixedPoint::min());
+        assert_eq!(big * big, FixedPoint::max());
+        assert_eq!(big * FixedPoint::from(-2.0), FixedPoint::min());
+        assert_eq!(FixedPoint::<2>::min().abs(), FixedPoint::max());
+        assert_eq!(
+            FixedPoint::<2>::min() * FixedPoint::min(),
+            FixedPoint::max()
+        );
+    }
+
+    fn draw<F: Floating>(fractal: &dyn FractalFunction<F>, poi: PoI<F>) -> Vec<PixelResult> {
+        const SIZE: u32 = 64;
+
+        let context = Context {
+            img_width: SIZE,
+            img_height: SIZE,
+            pinhole_step: F::from(1.0),
+            sampling: Sampling::Single,
+            coloring: Coloring::Rainbow,
+            interior: Interior::Black,
+            palette: None,
+            poi,
+        };
+
+        let mut pixels = vec![PixelResult::default(); (SIZE * SIZE) as usize];
+        let area = Area {
+            x: 0,
+            y: 0,
+            width: SIZE,
+            height: SIZE,
+            step: 1,
+        };
+        fractal.draw(&context, &area, &mut pixels);
+
+        pixels
+    }
+
+    fn poi<F: Floating>(pinhole_size: f64, limit: u32) -> PoI<F> {
+        PoI {
+            origin_x: F::from(-0.743643887037158704752191506114774),
+            origin_y: F::from(0.131825904205311970493132056385139),
+            pinhole_size: F::from(pinhole_size),
+            limit,
+        }
+    }
+
+    #[test]
+    fn mandelbrot_matches_f64() {
+        let fixed = draw(&Mandelbrot::<FixedPoint<2>>(PhantomData), poi(0.01, 1000));
+        let double = draw(&Mandelbrot::<f64>(PhantomData), poi(0.01, 1000));
+
+        // Different rounding, so few pixels on the boundary may differ
+        let same = fixed
+            .iter()
+            .zip(double.iter())
+            .filter(|(a, b)| a == b)
+            .count();
+        assert!(same as f64 / fixed.len() as f64 > 0.99);
+    }
+
+    // Deep enough for f64 to give up, so only the types with the precision to spare compete.
+    // Run with cargo +nightly bench
+    const BENCH_PINHOLE: f64 = 0.000000000000000000001;
+    const BENCH_LIMIT: u32 = 200;
+
+    #[bench]
+    fn mandelbrot_fixed_point(b: &mut Bencher) {
+        b.iter(|| {
+            draw(
+                &Mandelbrot::<FixedPoint<2>>(PhantomData),
+                poi(BENCH_PINHOLE, BENCH_LIMIT),
+            )
+        });
+    }
+
+    #[bench]
+    fn mandelbrot_big_float(b: &mut Bencher) {
+        b.iter(|| {
+            draw(
+                &Mandelbrot::<BigFloat<2>>(PhantomData),
+                poi(BENCH_PINHOLE, BENCH_LIMIT),
+            )
+        });
+    }
+
+    #[bench]
+    fn mandelbrot_quad(b: &mut Bencher) {
+        b.iter(|| {
+            draw(
+                &Mandelbrot::<Quad>(PhantomData),
+                poi(BENCH_PINHOLE, BENCH_LIMIT),
+            )
+        });
+    }
+
+    #[bench]
+    fn mandelbrot_rug(b: &mut Bencher) {
+        b.iter(|| draw(&MandelbrotRug, poi(BENCH_PINHOLE, BENCH_LIMIT)));
+    }
+}
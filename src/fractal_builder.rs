@@ -1,16 +1,31 @@
 use crate::{
-    executor::{Executor, ExecutorKind, Rayon},
-    fractals::{Floating, FractalFunction, Mandelbrot, PoI},
+    coloring::{Coloring, Interior},
+    executor::{Executor, ExecutorKind, MarianiSilver, Rayon, AUTO_ZOOM_STEP},
+    fractal_exotic::MultibrotReal,
+    fractals::{
+        BurningShip, Floating, FractalFunction, Julia, Mandelbrot, Multibrot, PoI, Statistics,
+    },
+    palette::Palette,
     pipe::Pipe,
+    poster,
+    supersampling::Sampling,
 };
-use std::marker::PhantomData;
+use std::{marker::PhantomData, path::Path, sync::Arc};
 
 #[derive(Debug)]
 pub struct Context<F> {
     pub img_width: u32,
     pub img_height: u32,
 
+    /// Pinhole is multiplied by that every frame, when auto zooming
     pub pinhole_step: F,
+    /// Samples taken per pixel, when coloring the final image
+    pub sampling: Sampling,
+    /// How pixels are colored, kernels compute only what it needs
+    pub coloring: Coloring,
+    pub interior: Interior,
+    /// Gradient colorings go along, rainbow if none
+    pub palette: Option<Arc<Palette>>,
     pub poi: PoI<F>,
 }
 pub struct Fractal<F: Floating> {
@@ -25,7 +40,11 @@ impl<F: Floating> Default for Fractal<F> {
             context: Context {
                 img_height: 608,
                 img_width: 608,
-                pinhole_step: F::from(1.0),
+                pinhole_step: F::from(AUTO_ZOOM_STEP),
+                sampling: Sampling::Single,
+                coloring: Coloring::Rainbow,
+                interior: Interior::Black,
+                palette: None,
                 poi: PoI {
                     origin_x: F::from(0.0),
                     origin_y: F::from(0.0),
@@ -39,6 +58,9 @@ impl<F: Floating> Default for Fractal<F> {
     }
 }
 
+// Main sets up a single fractal, the other options are there for whoever builds another one,
+// so they, and the enum variants only they would take, are allowed to go unused
+#[allow(dead_code)]
 impl<F: Floating> Fractal<F> {
     pub fn with(mut self, fractal: Box<dyn FractalFunction<F>>) -> Self {
         self.fractal_function = fractal;
@@ -46,8 +68,25 @@ impl<F: Floating> Fractal<F> {
         self
     }
 
-    pub fn julia(mut self) -> Self {
-        todo!();
+    /// Julia set of z^2 + c, for c = x + yi
+    pub fn julia(mut self, x: F, y: F) -> Self {
+        self.fractal_function = Box::new(Julia { x, y });
+
+        self
+    }
+
+    /// Burning Ship, the Mandelbrot set with z folded to the first quadrant
+    pub fn burning_ship(mut self) -> Self {
+        self.fractal_function = Box::new(BurningShip::<F>(PhantomData));
+
+        self
+    }
+
+    /// Multibrot set of z^power + c, commands move the power at runtime
+    pub fn multibrot(mut self, power: u32) -> Self {
+        self.fractal_function = Box::new(Multibrot::new(power));
+
+        self
     }
 
     /// This time use enum, because... why not
@@ -55,6 +94,7 @@ impl<F: Floating> Fractal<F> {
         match executor {
             ExecutorKind::SingleThread => todo!(),
             ExecutorKind::Rayon => self.executor = Box::new(Rayon),
+            ExecutorKind::MarianiSilver => self.executor = Box::new(MarianiSilver),
         }
 
         self
@@ -66,9 +106,60 @@ impl<F: Floating> Fractal<F> {
         self
     }
 
+    /// Grid, jittered and adaptive sampling take n x n samples, n has to be at least 1
+    pub fn with_sampling(mut self, sampling: Sampling) -> Self {
+        if let Sampling::Grid(n) | Sampling::Jittered(n) | Sampling::Adaptive(n) = sampling {
+            assert!(n > 0, "sampling needs at least one sample per pixel");
+        }
+        self.context.sampling = sampling;
+
+        self
+    }
+
+    pub fn with_coloring(mut self, coloring: Coloring) -> Self {
+        self.context.coloring = coloring;
+
+        self
+    }
+
+    pub fn with_interior(mut self, interior: Interior) -> Self {
+        self.context.interior = interior;
+
+        self
+    }
+
+    pub fn with_palette(mut self, palette: Palette) -> Self {
+        self.context.palette = Some(Arc::new(palette));
+
+        self
+    }
+
+    pub fn with_size(mut self, width: u32, height: u32) -> Self {
+        self.context.img_width = width;
+        self.context.img_height = height;
+
+        self
+    }
+
     pub fn start(self) -> Pipe {
         self.executor.execute(self.context, self.fractal_function)
     }
+
+    /// Renders single image straight to the PNG file, instead of starting the executor.
+    /// Memory does not grow with the height of the image, meant for posters.
+    pub fn render_to_file<P: AsRef<Path>>(self, path: P) -> Result<Statistics, png::EncodingError> {
+        poster::render(&self.context, &*self.fractal_function, path.as_ref())
+    }
+}
+
+#[allow(dead_code)]
+impl Fractal<f64> {
+    /// Multibrot set of any real power, moved by a tenth at runtime, powers morph into each other
+    pub fn multibrot_real(mut self, power: f64) -> Self {
+        self.fractal_function = Box::new(MultibrotReal::new(power));
+
+        self
+    }
 }
 
 #[cfg(test)]
@@ -89,4 +180,10 @@ mod tests {
 
         let _pipe = fractal.start();
     }
+
+    #[test]
+    #[should_panic(expected = "at least one sample")]
+    fn empty_sampling_grid_is_rejected() {
+        let _ = Fractal::<f64>::default().with_sampling(Sampling::Jittered(0));
+    }
 }
@@ -1,11 +1,13 @@
 ///! Here are fractal implementations which adoption to Teamplate Floating parameter
 ///! would take ages, hence we select one f64 and implement FractalFunction trait.
-use image::Rgb;
-use std::mem;
+use std::{marker::PhantomData, mem};
 extern crate crossbeam;
 extern crate num_cpus;
 use crate::{
-    fractals::{color_rainbow, FractalFunction},
+    fractals::{
+        bailout, distance_estimate, draw_orbits, interior_estimate, normal, Area, BurningShip,
+        Formula, FractalFunction, Mandelbrot, PixelResult, Statistics, PERIODICITY_EPSILONS,
+    },
     main,
 };
 use rug::Float;
@@ -23,10 +25,9 @@ impl FractalFunction<f64> for MandelbrotRug {
     fn draw(
         &self,
         context: &crate::fractal_builder::Context<f64>,
-        id: u32,
-        height: u32,
-        pixels: &mut [Rgb<u8>],
-    ) {
+        area: &Area,
+        pixels: &mut [PixelResult],
+    ) -> Statistics {
         const BIT_PRECISION: u32 = 64;
         let imgx = context.img_width;
         let imgy = context.img_height;
@@ -37,13 +38,15 @@ impl FractalFunction<f64> for MandelbrotRug {
         let origin_x = Float::with_val(BIT_PRECISION, context.poi.origin_x);
         let origin_y = Float::with_val(BIT_PRECISION, context.poi.origin_y);
 
-        let float_four = Float::with_val(BIT_PRECISION, 4.0);
+        // Escape point is the only extra this kernel gives, it needs the orbit far away
+        let escape_point = context.coloring.needs_escape_point();
+        let float_bailout = Float::with_val(BIT_PRECISION, bailout(escape_point));
 
         let center_y_offset = Float::with_val(BIT_PRECISION, &origin_y - &pinhole_center);
         let center_x_offset = Float::with_val(BIT_PRECISION, origin_x - &pinhole_center);
 
-        for pixel_y in 0..height {
-            let y_offset = pixel_y + id * height;
+        for pixel_y in 0..area.height {
+            let y_offset = area.y + pixel_y * area.step;
 
             // let y0 = origin_y + (y_offset as f64 / imgy as f64) * pinhole_size
             // - pinhole_center;
@@ -62,10 +65,11 @@ impl FractalFunction<f64> for MandelbrotRug {
             //     ),
             // ) - &pinhole_center;
 
-            for pixel_x in 0..context.img_width {
+            for pixel_x in 0..area.width {
+                let x_offset = area.x + pixel_x * area.step;
                 let x0 = Float::with_val(
                     BIT_PRECISION,
-                    &(pixel_x as f64 / imgx as f64) * &pinhole_size,
+                    &(x_offset as f64 / imgx as f64) * &pinhole_size,
                 ) + &center_x_offset;
 
                 // TODO: SLOWER!
@@ -85,7 +89,7 @@ impl FractalFunction<f64> for MandelbrotRug {
                 let mut y2 = Float::with_val(BIT_PRECISION, 0.0);
                 let mut sum = Float::with_val(BIT_PRECISION, 0.0);
 
-                while sum < float_four && iteration < context.poi.limit {
+                while sum < float_bailout && iteration < context.poi.limit {
                     // y = (x + x) * y + y0;
                     y = y.mul_add(&Float::with_val(BIT_PRECISION, &x + &x), &y0);
 
@@ -104,13 +108,92 @@ impl FractalFunction<f64> for MandelbrotRug {
                     iteration += 1;
                 }
 
-                pixels[(pixel_y * context.img_height + pixel_x) as usize] =
-                    color_rainbow(iteration, context.poi.limit);
+                let mut result = PixelResult {
+                    iteration,
+                    ..Default::default()
+                };
+
+                if escape_point && iteration < context.poi.limit {
+                    result.escape = (x.to_f64(), y.to_f64());
+                }
+
+                pixels[(pixel_y * area.width + pixel_x) as usize] = result;
             }
         }
+
+        Statistics::default()
     }
 }
 
+/// Multibrot set of z^power + c, for any real power, z is raised in the polar form.
+/// Argument of z is cut along the negative real axis, so are the bulbs of fractional powers.
+pub struct MultibrotReal {
+    power: f64,
+}
+
+/// Steps per unit of the power, when it is changed at runtime
+const POWER_STEPS: f64 = 10.0;
+
+impl MultibrotReal {
+    /// Powers at 1 or below are taken as 1 + one step, z + c escapes everywhere
+    pub fn new(power: f64) -> Self {
+        MultibrotReal {
+            power: power.max(1.0 + 1.0 / POWER_STEPS),
+        }
+    }
+
+    /// z^(power - 1) = |z|^(power - 1) * e^(i * (power - 1) * arg z)
+    fn power_below(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        let magnitude = (x * x + y * y).powf((self.power - 1.0) / 2.0);
+        let (sin, cos) = ((self.power - 1.0) * y.atan2(x)).sin_cos();
+
+        (magnitude * cos, magnitude * sin)
+    }
+}
+
+impl Formula<f64> for MultibrotReal {
+    fn degree(&self) -> f64 {
+        self.power
+    }
+
+    fn step(&self, (x, y): (f64, f64), _squares: (f64, f64), (x0, y0): (f64, f64)) -> (f64, f64) {
+        let (px, py) = self.power_below((x, y));
+
+        (px * x - py * y + x0, px * y + py * x + y0)
+    }
+
+    /// dz = power * z^(power - 1) * dz + 1
+    fn derivative(&self, z: (f64, f64), (dx, dy): (f64, f64)) -> Option<(f64, f64)> {
+        let (px, py) = self.power_below(z);
+        let (gx, gy) = (self.power * px, self.power * py);
+
+        Some((gx * dx - gy * dy + 1.0, gx * dy + gy * dx))
+    }
+}
+
+impl FractalFunction<f64> for MultibrotReal {
+    fn draw(
+        &self,
+        context: &crate::fractal_builder::Context<f64>,
+        area: &Area,
+        pixels: &mut [PixelResult],
+    ) -> Statistics {
+        draw_orbits(context, area, pixels, self)
+    }
+
+    fn step_power(&mut self, steps: i32) {
+        // Counted in whole steps, so integer powers come out exact
+        let power = ((self.power * POWER_STEPS).round() + steps as f64) / POWER_STEPS;
+        *self = MultibrotReal::new(power);
+    }
+}
+
+/// Traps and averages are not vectorized, SIMD kernels hand them over to the scalar ones,
+/// which follow the orbit
+fn vectorized(context: &crate::fractal_builder::Context<f64>) -> bool {
+    !context.coloring.needs_orbit()
+}
+
 /// Uses SIMD AVX2 intrinsic
 pub struct MandelbrotAvx2;
 
@@ -118,29 +201,44 @@ impl FractalFunction<f64> for MandelbrotAvx2 {
     fn draw(
         &self,
         context: &crate::fractal_builder::Context<f64>,
-        id: u32,
-        height: u32,
-        pixels: &mut [Rgb<u8>],
-    ) {
+        area: &Area,
+        pixels: &mut [PixelResult],
+    ) -> Statistics {
         if !is_x86_feature_detected!("avx2") {
             panic!("AVX2 not supported on this platform :(");
         }
 
+        if !vectorized(context) {
+            return Mandelbrot(PhantomData).draw(context, area, pixels);
+        }
+
         let imgx = context.img_width;
         let imgy = context.img_height;
 
+        let mut stats = Statistics::default();
+
         let pinhole_center = context.poi.pinhole_size / 2.0;
         let x0_offset = context.poi.origin_x - pinhole_center;
+        let derivative = context.coloring.needs_derivative();
+        let escape_point = context.coloring.needs_escape_point();
+        let bailout = bailout(context.coloring.needs_far_bailout());
+        // Shortcut does not find the cycle, interior has to be iterated then
+        let cycle = context.interior.needs_cycle();
 
         // SIMD part of code
         unsafe {
             // 4 doubles with bin representation of 0xff...
             let ff_mask = _mm256_cmp_pd(_mm256_set1_pd(1.0), _mm256_set1_pd(4.0), _CMP_LE_OQ);
+            // Only the sign bit set
+            let sign_mask = _mm256_set1_pd(-0.0);
+            let tolerance = _mm256_set1_pd(PERIODICITY_EPSILONS * f64::EPSILON);
+            let one = _mm256_set1_pd(1.0);
+            let two = _mm256_set1_pd(2.0);
 
             let mut mask;
 
-            for pixel_y in 0..height {
-                let y_offset = pixel_y + id * height;
+            for pixel_y in 0..area.height {
+                let y_offset = area.y + pixel_y * area.step;
                 let y0 = context.poi.origin_y
                     + (y_offset as f64 / imgy as f64) * context.poi.pinhole_size
                     - pinhole_center;
@@ -148,9 +246,15 @@ impl FractalFunction<f64> for MandelbrotAvx2 {
                 let y0 = _mm256_set1_pd(y0);
 
                 // Step by 4, on every iteration we take 4 floats at once
-                for pixel_x in (0..context.img_width).step_by(4) {
+                for pixel_x in (0..area.width).step_by(4) {
                     let mut iteration = [0, 0, 0, 0];
 
+                    // Width does not have to be multiple of 4, lanes past the end
+                    // repeat the last pixel, and are not stored
+                    let lanes = (area.width - pixel_x).min(4);
+                    let last = area.x + (area.width - 1) * area.step;
+                    let x_offset = area.x + pixel_x * area.step;
+
                     // let x0 = (pixel_x as f64 / imgx as f64) * self.pinhole_size + x0_offset;
                     // + x0_offset
                     let x0 = _mm256_add_pd(
@@ -159,10 +263,10 @@ impl FractalFunction<f64> for MandelbrotAvx2 {
                             // pixel_x as f64 / imgx as f64
                             _mm256_div_pd(
                                 _mm256_set_pd(
-                                    (pixel_x + 3) as f64,
-                                    (pixel_x + 2) as f64,
-                                    (pixel_x + 1) as f64,
-                                    pixel_x as f64,
+                                    (x_offset + 3 * area.step).min(last) as f64,
+                                    (x_offset + 2 * area.step).min(last) as f64,
+                                    (x_offset + 1 * area.step).min(last) as f64,
+                                    x_offset as f64,
                                 ),
                                 _mm256_set1_pd(imgx as f64),
                             ),
@@ -171,6 +275,27 @@ impl FractalFunction<f64> for MandelbrotAvx2 {
                         _mm256_set1_pd(x0_offset),
                     );
 
+                    // Bit per lane, set if the point is inside of the main cardioid or period-2 bulb
+                    let interior_lanes = if cycle {
+                        _mm256_setzero_pd()
+                    } else {
+                        avx2_in_cardioid_or_bulb(x0, y0)
+                    };
+                    let interior = _mm256_movemask_pd(interior_lanes);
+
+                    // Whole vector is inside, nothing to iterate. Otherwise lanes have to go
+                    // together, interior ones are just ignored, and do not count as shortcuts.
+                    if interior == 0b1111 {
+                        stats.interior_shortcut += lanes;
+                        for i in 0..lanes {
+                            pixels[(pixel_y * area.width + pixel_x + i) as usize] = PixelResult {
+                                iteration: context.poi.limit,
+                                ..Default::default()
+                            };
+                        }
+                        continue;
+                    }
+
                     // let mut x = 0.0;
                     let mut x = _mm256_setzero_pd();
                     // let mut y = 0.0;
@@ -182,7 +307,40 @@ impl FractalFunction<f64> for MandelbrotAvx2 {
                     // let mut sum = 0.0;
                     let mut sum = _mm256_setzero_pd();
 
+                    // Brent's cycle detection, same as in Mandelbrot<F>, all lanes at once
+                    let mut check_x = _mm256_setzero_pd();
+                    let mut check_y = _mm256_setzero_pd();
+                    let mut check_step = 0;
+                    let mut check_window = 1;
+                    let mut period = [0, 0, 0, 0];
+                    // Lanes known to never escape: interior ones, and the periodic, 0xff... if true
+                    let mut done = interior_lanes;
+
+                    // Derivative dz/dc, same as in Mandelbrot<F>
+                    let mut dx = _mm256_setzero_pd();
+                    let mut dy = _mm256_setzero_pd();
+                    let mut escaped = [false; 4];
+                    let mut distance = [0.0; 4];
+                    let mut normals = [(0.0, 0.0); 4];
+                    let mut escapes = [(0.0, 0.0); 4];
+
                     for i in 0..context.poi.limit {
+                        if derivative {
+                            // dz = 2 * z * dz + 1
+                            let dx_next = _mm256_add_pd(
+                                _mm256_mul_pd(
+                                    two,
+                                    _mm256_sub_pd(_mm256_mul_pd(x, dx), _mm256_mul_pd(y, dy)),
+                                ),
+                                one,
+                            );
+                            dy = _mm256_mul_pd(
+                                two,
+                                _mm256_add_pd(_mm256_mul_pd(x, dy), _mm256_mul_pd(y, dx)),
+                            );
+                            dx = dx_next;
+                        }
+
                         // y = (x + x) * y + y0;
                         // + y0
                         y = _mm256_add_pd(
@@ -227,26 +385,333 @@ impl FractalFunction<f64> for MandelbrotAvx2 {
                         // mk = _mm256_add_ps(_mm256_and_ps(mask, one), mk);
                         //
                         for i in 0..sum_unpacked.len() {
-                            iteration[i] = iteration[i] + (sum_unpacked[i] < 4.0) as u32;
+                            iteration[i] = iteration[i] + (sum_unpacked[i] < bailout) as u32;
                         }
 
-                        // sum < 4.0, _CMP_LE_OQ == Less-than-or-equal (ordered, non-signaling)
-                        mask = _mm256_cmp_pd(sum, _mm256_set1_pd(4.0), _CMP_LE_OQ);
+                        // Lanes keep iterating past the escape, estimate and z are taken right at it
+                        if derivative || escape_point {
+                            let x_unpacked: [f64; 4] = mem::transmute(x);
+                            let y_unpacked: [f64; 4] = mem::transmute(y);
+                            let dx_unpacked: [f64; 4] = mem::transmute(dx);
+                            let dy_unpacked: [f64; 4] = mem::transmute(dy);
+
+                            for lane in 0..4 {
+                                if !escaped[lane] && sum_unpacked[lane] >= bailout {
+                                    let (dx, dy) = (dx_unpacked[lane], dy_unpacked[lane]);
+
+                                    escaped[lane] = true;
+                                    if derivative {
+                                        distance[lane] = distance_estimate(
+                                            sum_unpacked[lane],
+                                            dx * dx + dy * dy,
+                                        );
+                                        normals[lane] =
+                                            normal(x_unpacked[lane], y_unpacked[lane], dx, dy);
+                                    }
+                                    if escape_point {
+                                        escapes[lane] = (x_unpacked[lane], y_unpacked[lane]);
+                                    }
+                                }
+                            }
+                        }
+
+                        // sum < bailout, _CMP_LE_OQ == Less-than-or-equal (ordered, non-signaling)
+                        mask = _mm256_cmp_pd(sum, _mm256_set1_pd(bailout), _CMP_LE_OQ);
                         // Mask will contain 0xfff... if pred is true, 0x000... otherwise
 
-                        // If mask is all 0, all points in the vector escaped 4.0 circle, break the loop
+                        // |x - check_x| < tolerance && |y - check_y| < tolerance,
+                        // abs is done by clearing the sign bit
+                        check_step += 1;
+                        let cycle = _mm256_and_pd(
+                            _mm256_cmp_pd(
+                                _mm256_andnot_pd(sign_mask, _mm256_sub_pd(x, check_x)),
+                                tolerance,
+                                _CMP_LT_OQ,
+                            ),
+                            _mm256_cmp_pd(
+                                _mm256_andnot_pd(sign_mask, _mm256_sub_pd(y, check_y)),
+                                tolerance,
+                                _CMP_LT_OQ,
+                            ),
+                        );
+
+                        // Only lanes still inside, which were not done before
+                        let new_cycle =
+                            _mm256_movemask_pd(_mm256_andnot_pd(done, _mm256_and_pd(cycle, mask)));
+
+                        if new_cycle != 0 {
+                            for lane in 0..4 {
+                                if new_cycle & (1 << lane) != 0 {
+                                    period[lane] = check_step;
+                                }
+                            }
+
+                            done = _mm256_or_pd(done, _mm256_and_pd(cycle, mask));
+                        }
+
+                        if check_step == check_window {
+                            check_step = 0;
+                            check_window *= 2;
+                            check_x = x;
+                            check_y = y;
+                        }
+
+                        // Lanes which are done are as good as escaped
+                        mask = _mm256_andnot_pd(done, mask);
+
+                        // If mask is all 0, all points in the vector escaped the bailout circle, break the loop
                         if _mm256_testz_pd(mask, ff_mask) == 1 {
                             break;
                         }
                     }
 
-                    for i in 0..4 {
-                        pixels[(pixel_y * context.img_height + pixel_x + i) as usize] =
-                            color_rainbow(iteration[i as usize], context.poi.limit);
+                    // Periodic lanes kept iterating, but they are still on the attracting cycle
+                    let x_unpacked: [f64; 4] = mem::transmute(x);
+                    let y_unpacked: [f64; 4] = mem::transmute(y);
+                    let x0_unpacked: [f64; 4] = mem::transmute(x0);
+                    let y0_unpacked: [f64; 4] = mem::transmute(y0);
+
+                    for i in 0..lanes as usize {
+                        let mut result = PixelResult {
+                            iteration: iteration[i],
+                            period: period[i],
+                            distance: distance[i],
+                            multiplier: 0.0,
+                            normal: normals[i],
+                            escape: escapes[i],
+                            ..Default::default()
+                        };
+
+                        if cycle && result.period != 0 {
+                            let (period, multiplier, distance) = interior_estimate(
+                                x_unpacked[i],
+                                y_unpacked[i],
+                                x0_unpacked[i],
+                                y0_unpacked[i],
+                                result.period,
+                            );
+                            result.period = period;
+                            result.multiplier = multiplier;
+                            result.distance = distance;
+                        }
+
+                        if interior & (1 << i) != 0 {
+                            result.iteration = context.poi.limit;
+                        } else if result.period != 0 {
+                            result.iteration = context.poi.limit;
+                            stats.periodic += 1;
+                        }
+
+                        pixels[(pixel_y * area.width + pixel_x + i as u32) as usize] = result;
+                    }
+                }
+            }
+        } // unsafe
+
+        stats
+    }
+}
+
+/// Vector version of fractals::in_cardioid_or_bulb, lanes of the result are 0xff... if true
+unsafe fn avx2_in_cardioid_or_bulb(x0: __m256d, y0: __m256d) -> __m256d {
+    let y2 = _mm256_mul_pd(y0, y0);
+
+    // q = (x - 1/4)^2 + y^2, inside if q * (q + (x - 1/4)) <= y^2 / 4
+    let x_quarter = _mm256_sub_pd(x0, _mm256_set1_pd(0.25));
+    let q = _mm256_add_pd(_mm256_mul_pd(x_quarter, x_quarter), y2);
+    let cardioid = _mm256_cmp_pd(
+        _mm256_mul_pd(q, _mm256_add_pd(q, x_quarter)),
+        _mm256_mul_pd(_mm256_set1_pd(0.25), y2),
+        _CMP_LE_OQ,
+    );
+
+    // (x + 1)^2 + y^2 <= 1/16
+    let x_one = _mm256_add_pd(x0, _mm256_set1_pd(1.0));
+    let bulb = _mm256_cmp_pd(
+        _mm256_add_pd(_mm256_mul_pd(x_one, x_one), y2),
+        _mm256_set1_pd(0.0625),
+        _CMP_LE_OQ,
+    );
+
+    _mm256_or_pd(cardioid, bulb)
+}
+
+/// Burning Ship on AVX2, same as BurningShip<f64>, iterations are counted the same way
+#[allow(dead_code)]
+pub struct BurningShipAvx2;
+
+impl FractalFunction<f64> for BurningShipAvx2 {
+    fn draw(
+        &self,
+        context: &crate::fractal_builder::Context<f64>,
+        area: &Area,
+        pixels: &mut [PixelResult],
+    ) -> Statistics {
+        if !is_x86_feature_detected!("avx2") {
+            panic!("AVX2 not supported on this platform :(");
+        }
+
+        if !vectorized(context) {
+            return BurningShip(PhantomData).draw(context, area, pixels);
+        }
+
+        let imgx = context.img_width;
+        let imgy = context.img_height;
+
+        let mut stats = Statistics::default();
+
+        let pinhole_center = context.poi.pinhole_size / 2.0;
+        let x0_offset = context.poi.origin_x - pinhole_center;
+        let y0_offset = context.poi.origin_y - pinhole_center;
+        let escape_point = context.coloring.needs_escape_point();
+        let bailout = bailout(context.coloring.needs_far_bailout());
+
+        unsafe {
+            // Only the sign bit set, clearing it is abs()
+            let sign_mask = _mm256_set1_pd(-0.0);
+            let tolerance = _mm256_set1_pd(PERIODICITY_EPSILONS * f64::EPSILON);
+
+            for pixel_y in 0..area.height {
+                let y_offset = area.y + pixel_y * area.step;
+                let y0 = (y_offset as f64 / imgy as f64) * context.poi.pinhole_size + y0_offset;
+
+                let y0 = _mm256_set1_pd(y0);
+
+                for pixel_x in (0..area.width).step_by(4) {
+                    // Lanes past the end repeat the last pixel, and are not stored
+                    let lanes = (area.width - pixel_x).min(4);
+                    let last = area.x + (area.width - 1) * area.step;
+                    let x_offset = area.x + pixel_x * area.step;
+
+                    let x0 = _mm256_add_pd(
+                        _mm256_mul_pd(
+                            _mm256_div_pd(
+                                _mm256_set_pd(
+                                    (x_offset + 3 * area.step).min(last) as f64,
+                                    (x_offset + 2 * area.step).min(last) as f64,
+                                    (x_offset + 1 * area.step).min(last) as f64,
+                                    x_offset as f64,
+                                ),
+                                _mm256_set1_pd(imgx as f64),
+                            ),
+                            _mm256_set1_pd(context.poi.pinhole_size),
+                        ),
+                        _mm256_set1_pd(x0_offset),
+                    );
+
+                    let mut x = _mm256_setzero_pd();
+                    let mut y = _mm256_setzero_pd();
+                    let mut x2 = _mm256_setzero_pd();
+                    let mut y2 = _mm256_setzero_pd();
+
+                    let mut check_x = _mm256_setzero_pd();
+                    let mut check_y = _mm256_setzero_pd();
+                    let mut check_step = 0;
+                    let mut check_window = 1;
+
+                    let mut iteration = [0u32; 4];
+                    let mut period = [0; 4];
+                    let mut escapes = [(0.0, 0.0); 4];
+                    // Bit per lane, set while the lane is still iterating
+                    let mut active = 0b1111;
+
+                    for _ in 0..context.poi.limit {
+                        // Counted before the step, as the scalar loop does
+                        for lane in 0..4 {
+                            iteration[lane] += ((active >> lane) & 1) as u32;
+                        }
+
+                        // Squares do not care about the sign, only the cross term does
+                        let ax = _mm256_andnot_pd(sign_mask, x);
+                        let ay = _mm256_andnot_pd(sign_mask, y);
+
+                        // y = (|x| + |x|) * |y| + y0;
+                        y = _mm256_add_pd(_mm256_mul_pd(_mm256_add_pd(ax, ax), ay), y0);
+                        // x = x2 - y2 + x0;
+                        x = _mm256_add_pd(_mm256_sub_pd(x2, y2), x0);
+
+                        x2 = _mm256_mul_pd(x, x);
+                        y2 = _mm256_mul_pd(y, y);
+                        let sum = _mm256_add_pd(x2, y2);
+
+                        let inside = _mm256_movemask_pd(_mm256_cmp_pd(
+                            sum,
+                            _mm256_set1_pd(bailout),
+                            _CMP_LT_OQ,
+                        ));
+
+                        // |x - check_x| < tolerance && |y - check_y| < tolerance
+                        check_step += 1;
+                        let cycle = _mm256_movemask_pd(_mm256_and_pd(
+                            _mm256_cmp_pd(
+                                _mm256_andnot_pd(sign_mask, _mm256_sub_pd(x, check_x)),
+                                tolerance,
+                                _CMP_LT_OQ,
+                            ),
+                            _mm256_cmp_pd(
+                                _mm256_andnot_pd(sign_mask, _mm256_sub_pd(y, check_y)),
+                                tolerance,
+                                _CMP_LT_OQ,
+                            ),
+                        ));
+
+                        // Cycle is checked before the escape, as the scalar loop does
+                        let new_cycle = active & cycle;
+                        let new_escape = active & !inside & !new_cycle;
+
+                        for lane in 0..4 {
+                            if new_cycle & (1 << lane) != 0 {
+                                period[lane] = check_step;
+                            }
+                        }
+
+                        if escape_point && new_escape != 0 {
+                            let x_unpacked: [f64; 4] = mem::transmute(x);
+                            let y_unpacked: [f64; 4] = mem::transmute(y);
+
+                            // Escape at the limit is taken as inside, as the scalar loop does
+                            for lane in 0..4 {
+                                if new_escape & (1 << lane) != 0
+                                    && iteration[lane] < context.poi.limit
+                                {
+                                    escapes[lane] = (x_unpacked[lane], y_unpacked[lane]);
+                                }
+                            }
+                        }
+
+                        active &= inside & !new_cycle;
+                        if active == 0 {
+                            break;
+                        }
+
+                        if check_step == check_window {
+                            check_step = 0;
+                            check_window *= 2;
+                            check_x = x;
+                            check_y = y;
+                        }
+                    }
+
+                    for i in 0..lanes as usize {
+                        let mut result = PixelResult {
+                            iteration: iteration[i],
+                            period: period[i],
+                            escape: escapes[i],
+                            ..Default::default()
+                        };
+
+                        if result.period != 0 {
+                            result.iteration = context.poi.limit;
+                            stats.periodic += 1;
+                        }
+
+                        pixels[(pixel_y * area.width + pixel_x + i as u32) as usize] = result;
                     }
                 }
             }
         } // unsafe
+
+        stats
     }
 }
 
@@ -257,27 +722,38 @@ impl FractalFunction<f64> for MandelbrotAvx512 {
     fn draw(
         &self,
         context: &crate::fractal_builder::Context<f64>,
-        id: u32,
-        height: u32,
-        pixels: &mut [Rgb<u8>],
-    ) {
+        area: &Area,
+        pixels: &mut [PixelResult],
+    ) -> Statistics {
         if !is_x86_feature_detected!("avx512f") {
             panic!("avx512f not supported on this platform :(");
         }
 
+        if !vectorized(context) {
+            return Mandelbrot(PhantomData).draw(context, area, pixels);
+        }
+
         let imgx = context.img_width;
         let imgy = context.img_height;
 
+        let mut stats = Statistics::default();
+
         let pinhole_center = context.poi.pinhole_size / 2.0;
         let x0_offset = context.poi.origin_x - pinhole_center;
+        let derivative = context.coloring.needs_derivative();
+        let escape_point = context.coloring.needs_escape_point();
+        let bailout = bailout(context.coloring.needs_far_bailout());
+        // Shortcut does not find the cycle, interior has to be iterated then
+        let cycle = context.interior.needs_cycle();
 
         // SIMD part of code
         unsafe {
-            // 4 doubles with bin representation of 0xff...
-            let ff_mask = _mm512_cmp_pd_mask(_mm512_set1_pd(1.0), _mm512_set1_pd(4.0), _CMP_LE_OQ);
+            let tolerance = _mm512_set1_pd(PERIODICITY_EPSILONS * f64::EPSILON);
+            let one = _mm512_set1_pd(1.0);
+            let two = _mm512_set1_pd(2.0);
 
-            for pixel_y in 0..height {
-                let y_offset = pixel_y + id * height;
+            for pixel_y in 0..area.height {
+                let y_offset = area.y + pixel_y * area.step;
                 let y0 = context.poi.origin_y
                     + (y_offset as f64 / imgy as f64) * context.poi.pinhole_size
                     - pinhole_center;
@@ -285,9 +761,15 @@ impl FractalFunction<f64> for MandelbrotAvx512 {
                 let y0 = _mm512_set1_pd(y0);
 
                 // Step by 8, on every iteration we take 8 floats at once
-                for pixel_x in (0..context.img_width).step_by(8) {
+                for pixel_x in (0..area.width).step_by(8) {
                     let mut iteration = [0, 0, 0, 0, 0, 0, 0, 0];
 
+                    // Width does not have to be multiple of 8, lanes past the end
+                    // repeat the last pixel, and are not stored
+                    let lanes = (area.width - pixel_x).min(8);
+                    let last = area.x + (area.width - 1) * area.step;
+                    let x_offset = area.x + pixel_x * area.step;
+
                     // let x0 = (pixel_x as f64 / imgx as f64) * self.pinhole_size + x0_offset;
                     // + x0_offset
                     let x0 = _mm512_add_pd(
@@ -296,14 +778,14 @@ impl FractalFunction<f64> for MandelbrotAvx512 {
                             // pixel_x as f64 / imgx as f64
                             _mm512_div_pd(
                                 _mm512_set_pd(
-                                    (pixel_x + 7) as f64,
-                                    (pixel_x + 6) as f64,
-                                    (pixel_x + 5) as f64,
-                                    (pixel_x + 4) as f64,
-                                    (pixel_x + 3) as f64,
-                                    (pixel_x + 2) as f64,
-                                    (pixel_x + 1) as f64,
-                                    pixel_x as f64,
+                                    (x_offset + 7 * area.step).min(last) as f64,
+                                    (x_offset + 6 * area.step).min(last) as f64,
+                                    (x_offset + 5 * area.step).min(last) as f64,
+                                    (x_offset + 4 * area.step).min(last) as f64,
+                                    (x_offset + 3 * area.step).min(last) as f64,
+                                    (x_offset + 2 * area.step).min(last) as f64,
+                                    (x_offset + 1 * area.step).min(last) as f64,
+                                    x_offset as f64,
                                 ),
                                 _mm512_set1_pd(imgx as f64),
                             ),
@@ -312,6 +794,26 @@ impl FractalFunction<f64> for MandelbrotAvx512 {
                         _mm512_set1_pd(x0_offset),
                     );
 
+                    // Bit per lane, set if the point is inside of the main cardioid or period-2 bulb
+                    let interior = if cycle {
+                        0
+                    } else {
+                        avx512_in_cardioid_or_bulb(x0, y0)
+                    };
+
+                    // Whole vector is inside, nothing to iterate. Otherwise lanes have to go
+                    // together, interior ones are just ignored, and do not count as shortcuts.
+                    if interior == 0xff {
+                        stats.interior_shortcut += lanes;
+                        for i in 0..lanes {
+                            pixels[(pixel_y * area.width + pixel_x + i) as usize] = PixelResult {
+                                iteration: context.poi.limit,
+                                ..Default::default()
+                            };
+                        }
+                        continue;
+                    }
+
                     // let mut x = 0.0;
                     let mut x = _mm512_setzero_pd();
                     // let mut y = 0.0;
@@ -323,11 +825,44 @@ impl FractalFunction<f64> for MandelbrotAvx512 {
                     // let mut sum = 0.0;
                     let mut sum = _mm512_setzero_pd();
 
+                    // Brent's cycle detection, same as in Mandelbrot<F>, all lanes at once
+                    let mut check_x = _mm512_setzero_pd();
+                    let mut check_y = _mm512_setzero_pd();
+                    let mut check_step = 0;
+                    let mut check_window = 1;
+                    let mut period = [0, 0, 0, 0, 0, 0, 0, 0];
+                    // Lanes known to never escape: interior ones, and the periodic, bit per lane
+                    let mut done = interior;
+
+                    // Derivative dz/dc, same as in Mandelbrot<F>
+                    let mut dx = _mm512_setzero_pd();
+                    let mut dy = _mm512_setzero_pd();
+                    let mut escaped: __mmask8 = 0;
+                    let mut distance = [0.0; 8];
+                    let mut normals = [(0.0, 0.0); 8];
+                    let mut escapes = [(0.0, 0.0); 8];
+
                     // TODO: try to change to range loop, should be no difference
                     let mut i = 0;
                     while i < context.poi.limit {
                         i += 1;
 
+                        if derivative {
+                            // dz = 2 * z * dz + 1
+                            let dx_next = _mm512_add_pd(
+                                _mm512_mul_pd(
+                                    two,
+                                    _mm512_sub_pd(_mm512_mul_pd(x, dx), _mm512_mul_pd(y, dy)),
+                                ),
+                                one,
+                            );
+                            dy = _mm512_mul_pd(
+                                two,
+                                _mm512_add_pd(_mm512_mul_pd(x, dy), _mm512_mul_pd(y, dx)),
+                            );
+                            dx = dx_next;
+                        }
+
                         // y = (x + x) * y + y0;
                         // + y0
                         y = _mm512_add_pd(
@@ -368,25 +903,151 @@ impl FractalFunction<f64> for MandelbrotAvx512 {
                         // _mm512_add_pd(_mm512_and_pd(mask, ff_mask), iteration_test);
                         // Returns NaN instead of 1.0 :\
                         for i in 0..sum_unpacked.len() {
-                            iteration[i] = iteration[i] + (sum_unpacked[i] < 4.0) as u32;
+                            iteration[i] = iteration[i] + (sum_unpacked[i] < bailout) as u32;
                         }
 
-                        // sum < 4.0, _CMP_LE_OQ == Less-than-or-equal (ordered, non-signaling)
-                        let mask = _mm512_cmp_pd_mask(sum, _mm512_set1_pd(4.0), _CMP_LE_OQ);
+                        // sum < bailout, _CMP_LE_OQ == Less-than-or-equal (ordered, non-signaling)
+                        let mask = _mm512_cmp_pd_mask(sum, _mm512_set1_pd(bailout), _CMP_LE_OQ);
                         // Mask will contain 0x1 per element if pred is true
 
-                        // If mask is all 0, all points in the vector escaped 4.0 circle, break the loop
-                        if mask == 0 {
+                        // Lanes keep iterating past the escape, estimate and z are taken right at it
+                        if derivative || escape_point {
+                            let new_escape = !mask & !escaped & !done;
+
+                            if new_escape != 0 {
+                                let x_unpacked: [f64; 8] = mem::transmute(x);
+                                let y_unpacked: [f64; 8] = mem::transmute(y);
+                                let dx_unpacked: [f64; 8] = mem::transmute(dx);
+                                let dy_unpacked: [f64; 8] = mem::transmute(dy);
+
+                                for lane in 0..8 {
+                                    if new_escape & (1 << lane) != 0 {
+                                        let (dx, dy) = (dx_unpacked[lane], dy_unpacked[lane]);
+
+                                        if derivative {
+                                            distance[lane] = distance_estimate(
+                                                sum_unpacked[lane],
+                                                dx * dx + dy * dy,
+                                            );
+                                            normals[lane] =
+                                                normal(x_unpacked[lane], y_unpacked[lane], dx, dy);
+                                        }
+                                        if escape_point {
+                                            escapes[lane] = (x_unpacked[lane], y_unpacked[lane]);
+                                        }
+                                    }
+                                }
+
+                                escaped |= new_escape;
+                            }
+                        }
+
+                        // |x - check_x| < tolerance && |y - check_y| < tolerance
+                        check_step += 1;
+                        let cycle = _mm512_cmp_pd_mask(
+                            _mm512_abs_pd(_mm512_sub_pd(x, check_x)),
+                            tolerance,
+                            _CMP_LT_OQ,
+                        ) & _mm512_cmp_pd_mask(
+                            _mm512_abs_pd(_mm512_sub_pd(y, check_y)),
+                            tolerance,
+                            _CMP_LT_OQ,
+                        );
+
+                        // Only lanes still inside, which were not done before
+                        let new_cycle = cycle & mask & !done;
+
+                        if new_cycle != 0 {
+                            for lane in 0..8 {
+                                if new_cycle & (1 << lane) != 0 {
+                                    period[lane] = check_step;
+                                }
+                            }
+
+                            done |= new_cycle;
+                        }
+
+                        if check_step == check_window {
+                            check_step = 0;
+                            check_window *= 2;
+                            check_x = x;
+                            check_y = y;
+                        }
+
+                        // If mask is all 0, all points in the vector escaped the bailout circle,
+                        // or are done, break the loop
+                        if mask & !done == 0 {
                             break;
                         }
                     }
 
-                    for i in 0..8 {
-                        pixels[(pixel_y * context.img_height + pixel_x + i) as usize] =
-                            color_rainbow(iteration[i as usize], context.poi.limit);
+                    // Periodic lanes kept iterating, but they are still on the attracting cycle
+                    let x_unpacked: [f64; 8] = mem::transmute(x);
+                    let y_unpacked: [f64; 8] = mem::transmute(y);
+                    let x0_unpacked: [f64; 8] = mem::transmute(x0);
+                    let y0_unpacked: [f64; 8] = mem::transmute(y0);
+
+                    for i in 0..lanes as usize {
+                        let mut result = PixelResult {
+                            iteration: iteration[i],
+                            period: period[i],
+                            distance: distance[i],
+                            multiplier: 0.0,
+                            normal: normals[i],
+                            escape: escapes[i],
+                            ..Default::default()
+                        };
+
+                        if cycle && result.period != 0 {
+                            let (period, multiplier, distance) = interior_estimate(
+                                x_unpacked[i],
+                                y_unpacked[i],
+                                x0_unpacked[i],
+                                y0_unpacked[i],
+                                result.period,
+                            );
+                            result.period = period;
+                            result.multiplier = multiplier;
+                            result.distance = distance;
+                        }
+
+                        if interior & (1 << i) != 0 {
+                            result.iteration = context.poi.limit;
+                        } else if result.period != 0 {
+                            result.iteration = context.poi.limit;
+                            stats.periodic += 1;
+                        }
+
+                        pixels[(pixel_y * area.width + pixel_x + i as u32) as usize] = result;
                     }
                 }
             } // unsafe
         }
+
+        stats
     }
 }
+
+/// Vector version of fractals::in_cardioid_or_bulb, bit per lane
+unsafe fn avx512_in_cardioid_or_bulb(x0: __m512d, y0: __m512d) -> __mmask8 {
+    let y2 = _mm512_mul_pd(y0, y0);
+
+    // q = (x - 1/4)^2 + y^2, inside if q * (q + (x - 1/4)) <= y^2 / 4
+    let x_quarter = _mm512_sub_pd(x0, _mm512_set1_pd(0.25));
+    let q = _mm512_add_pd(_mm512_mul_pd(x_quarter, x_quarter), y2);
+    let cardioid = _mm512_cmp_pd_mask(
+        _mm512_mul_pd(q, _mm512_add_pd(q, x_quarter)),
+        _mm512_mul_pd(_mm512_set1_pd(0.25), y2),
+        _CMP_LE_OQ,
+    );
+
+    // (x + 1)^2 + y^2 <= 1/16
+    let x_one = _mm512_add_pd(x0, _mm512_set1_pd(1.0));
+    let bulb = _mm512_cmp_pd_mask(
+        _mm512_add_pd(_mm512_mul_pd(x_one, x_one), y2),
+        _mm512_set1_pd(0.0625),
+        _CMP_LE_OQ,
+    );
+
+    cardioid | bulb
+}
@@ -4,9 +4,7 @@ use std::{
     ops::{Add, AddAssign, Mul, MulAssign, Sub, SubAssign},
 };
 
-use image::Rgb;
-
-use crate::fractal_builder::Context;
+use crate::{coloring::Average, fractal_builder::Context};
 
 /// Trait defining underlying floating type
 // Send and Sync to safely pass type over threads
@@ -24,10 +22,37 @@ pub trait Floating = From<f64>
     + Sub<Output = Self>
     + SubAssign
     + PartialOrd
+    + Epsilon
+    + Abs
+    // Lossy, for values which do not need the precision, like the distance estimate
+    + Into<f64>
     + Send
     + Sync
     + Debug;
 
+/// Machine epsilon - difference between 1.0 and the next representable number.
+/// Tells how close two numbers can get, before they are considered the same.
+pub trait Epsilon {
+    fn epsilon() -> Self;
+}
+
+impl Epsilon for f64 {
+    fn epsilon() -> Self {
+        f64::EPSILON
+    }
+}
+
+/// Absolute value, folds like the Burning Ship need it
+pub trait Abs {
+    fn abs(self) -> Self;
+}
+
+impl Abs for f64 {
+    fn abs(self) -> Self {
+        f64::abs(self)
+    }
+}
+
 /// PoI - point of interest on a complex plane
 #[derive(Debug, Default)]
 pub struct PoI<Floating> {
@@ -37,12 +62,543 @@ pub struct PoI<Floating> {
     pub limit: u32,
 }
 
+/// Outcome of the iteration for a single pixel, colorizing is done out of it
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PixelResult {
+    /// Equals to the limit, if point belongs to the set
+    pub iteration: u32,
+    /// Length of the orbit cycle, 0 if none was found
+    pub period: u32,
+    /// Estimated distance to the boundary of the set, on the complex plane, 0 if the coloring
+    /// does not need it. Outside out of the derivative dz/dc, inside out of the cycle.
+    pub distance: f64,
+    /// Magnitude of the cycle multiplier, 0 at the nucleus of the component, 1 at its boundary.
+    /// 0 if no cycle was found, or the coloring does not need it.
+    pub multiplier: f64,
+    /// Unit normal of the surface the escaped orbit makes, for lighting, (0, 0) if not needed
+    pub normal: (f64, f64),
+    /// Closest the orbit came to the trap of the coloring, and the iteration it happened,
+    /// 0 if there is no trap
+    pub trap_distance: f64,
+    pub trap_iteration: u32,
+    /// Average of the coloring along the orbit, smoothed between the last two iterations,
+    /// within 0..1. 0 if the coloring does not average.
+    pub average: f64,
+    /// Point z the orbit escaped at, (0, 0) if the coloring does not need it
+    pub escape: (f64, f64),
+}
+
+/// Rectangle of pixels on the image, pixels of it are stored row by row
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Area {
+    pub x: u32,
+    pub y: u32,
+    /// Number of pixels in a row, not the distance on the image
+    pub width: u32,
+    pub height: u32,
+    /// Only every step-th pixel is drawn, in both directions, 1 draws all of them
+    pub step: u32,
+}
+
+/// Numbers gathered during drawing, every chunk reports its own,
+/// Executor sums them up for the whole frame.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Statistics {
+    /// Pixels found inside of the main cardioid or period-2 bulb, without iterating
+    pub interior_shortcut: u32,
+    /// Pixels which orbit fell into a cycle, before reaching the limit
+    pub periodic: u32,
+    /// Pixels filled by the subdivision, without iterating
+    pub filled: u32,
+    /// Pixels sampled again by the adaptive supersampling, since iterations vary around them
+    pub refined: u32,
+}
+
+impl Add for Statistics {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Statistics {
+            interior_shortcut: self.interior_shortcut + rhs.interior_shortcut,
+            periodic: self.periodic + rhs.periodic,
+            filled: self.filled + rhs.filled,
+            refined: self.refined + rhs.refined,
+        }
+    }
+}
+
 /// Interface required for fractal to be implemented for drawing purposes
 /// Struct which implements this trait, are constrained to be Sync + Send,
 /// That impacts also F type.
 pub trait FractalFunction<F: Floating>: Send + Sync {
     // &self to have safe object
-    fn draw(&self, context: &Context<F>, id: u32, height: u32, pixels: &mut [Rgb<u8>]);
+    /// Draws given area of the image, pixels has area.width * area.height elements
+    fn draw(&self, context: &Context<F>, area: &Area, pixels: &mut [PixelResult]) -> Statistics;
+
+    /// Moves the exponent of z by that many steps, for fractals which have one to change
+    fn step_power(&mut self, _steps: i32) {}
+}
+
+/// Points inside of the main cardioid and period-2 bulb never escape,
+/// that can be checked analytically, instead of iterating up to the limit.
+/// https://en.wikipedia.org/wiki/Plotting_algorithms_for_the_Mandelbrot_set#Cardioid_/_bulb_checking
+pub fn in_cardioid_or_bulb<F: Floating>(x0: F, y0: F) -> bool {
+    let y2 = y0 * y0;
+
+    // q = (x - 1/4)^2 + y^2, inside if q * (q + (x - 1/4)) <= y^2 / 4
+    let x_quarter = x0 - F::from(0.25);
+    let q = x_quarter * x_quarter + y2;
+    if q * (q + x_quarter) <= F::from(0.25) * y2 {
+        return true;
+    }
+
+    // (x + 1)^2 + y^2 <= 1/16
+    let x_one = x0 + F::from(1.0);
+    x_one * x_one + y2 <= F::from(0.0625)
+}
+
+/// Orbit is compared with the saved point, tolerance is that many epsilons.
+/// Rounding errors of few operations per iteration have to fit in there.
+pub const PERIODICITY_EPSILONS: f64 = 8.0;
+
+/// Squared escape radius, when the distance is estimated, or the orbit averaged. The estimate
+/// is off by orders of magnitude when the orbit escapes just past 2, it needs to get far away
+/// from the set. Averages need the last terms to settle, or bands show up, and the cells
+/// of the decomposition get straight.
+pub const DISTANCE_BAILOUT: f64 = 1e6;
+
+/// Orbit escapes when the squared magnitude of z reaches that, far when it has to settle
+pub fn bailout(far: bool) -> f64 {
+    if far {
+        DISTANCE_BAILOUT
+    } else {
+        4.0
+    }
+}
+
+/// Exterior distance estimate, 2 * |z| * ln|z| / |dz|, out of squared magnitudes of the
+/// escaped z and its derivative dz/dc. True distance is between a quarter of it and itself.
+/// https://en.wikipedia.org/wiki/Plotting_algorithms_for_the_Mandelbrot_set#Distance_estimates
+pub fn distance_estimate(z2: f64, dz2: f64) -> f64 {
+    (z2 / dz2).sqrt() * z2.ln()
+}
+
+/// Cycle point found by Newton's method comes back that close, in epsilons, after a round.
+/// Looser than PERIODICITY_EPSILONS, rounding errors of the whole round have to fit in there.
+const CYCLE_EPSILONS: f64 = 1024.0;
+
+/// Newton's method converges quadratically, orbit is already close to the cycle
+const NEWTON_STEPS: u32 = 2;
+
+/// Derivatives of z, taken along one round of the cycle, z = z^2 + c repeated period times
+struct Round<F> {
+    z: (F, F),
+    /// dz/dz, the multiplier after the whole round
+    dz: (F, F),
+    dc: (F, F),
+    dzdz: (F, F),
+    dcdz: (F, F),
+}
+
+fn round<F: Floating>(z: (F, F), c: (F, F), period: u32) -> Round<F> {
+    let zero = F::from(0.0);
+    let one = F::from(1.0);
+
+    let mut round = Round {
+        z,
+        dz: (one, zero),
+        dc: (zero, zero),
+        dzdz: (zero, zero),
+        dcdz: (zero, zero),
+    };
+
+    for _ in 0..period {
+        let Round {
+            z,
+            dz,
+            dc,
+            dzdz,
+            dcdz,
+        } = round;
+        let two_z = (z.0 + z.0, z.1 + z.1);
+
+        // dcdz = 2 * z * dcdz + 2 * dc * dz
+        let (a, b) = (complex_mul(two_z, dcdz), complex_mul(dc, dz));
+        round.dcdz = (a.0 + b.0 + b.0, a.1 + b.1 + b.1);
+        // dzdz = 2 * dz * dz + 2 * z * dzdz
+        let (a, b) = (complex_mul(dz, dz), complex_mul(two_z, dzdz));
+        round.dzdz = (a.0 + a.0 + b.0, a.1 + a.1 + b.1);
+        // dc = 2 * z * dc + 1
+        let a = complex_mul(two_z, dc);
+        round.dc = (a.0 + one, a.1);
+        // dz = 2 * z * dz
+        round.dz = complex_mul(two_z, dz);
+        // z = z^2 + c
+        let a = complex_mul(z, z);
+        round.z = (a.0 + c.0, a.1 + c.1);
+    }
+
+    round
+}
+
+/// Period, multiplier magnitude and interior distance estimate of the attracting cycle,
+/// out of the orbit point z which fell into it, and the period given by cycle detection.
+/// https://en.wikipedia.org/wiki/Plotting_algorithms_for_the_Mandelbrot_set#Interior_distance_estimation
+pub fn interior_estimate<F: Floating>(x: F, y: F, x0: F, y0: F, period: u32) -> (u32, f64, f64) {
+    let c = (x0, y0);
+    let mut z = (x, y);
+
+    // Orbit is only close to the cycle, Newton's method on f^period(z) - z = 0 puts it right on
+    // it. Only the correction is computed in f64, it is tiny, so its precision is enough.
+    for _ in 0..NEWTON_STEPS {
+        let round = round(z, c, period);
+        let (re, im): (f64, f64) = (round.dz.0.into() - 1.0, round.dz.1.into());
+        let norm = re * re + im * im;
+
+        if norm == 0.0 {
+            break;
+        }
+
+        let inverse = (F::from(re / norm), F::from(-im / norm));
+        let step = complex_mul((round.z.0 - z.0, round.z.1 - z.1), inverse);
+        z = (z.0 - step.0, z.1 - step.1);
+    }
+
+    // Cycle detection can give a multiple of the period, when points of the cycle
+    // come close to each other slower than the orbit converges
+    let tolerance = F::from(CYCLE_EPSILONS) * F::epsilon();
+    let period = (1..period)
+        .filter(|q| period % q == 0)
+        .find(|&q| {
+            let next = round(z, c, q).z;
+            close_enough(next.0, z.0, tolerance) && close_enough(next.1, z.1, tolerance)
+        })
+        .unwrap_or(period);
+
+    // Only ratios are left, they do not need the precision of F
+    let to_f64 = |(re, im): (F, F)| -> (f64, f64) { (re.into(), im.into()) };
+    let round = round(z, c, period);
+    let (dz, dc, dzdz, dcdz) = (
+        to_f64(round.dz),
+        to_f64(round.dc),
+        to_f64(round.dzdz),
+        to_f64(round.dcdz),
+    );
+
+    let multiplier2 = dz.0 * dz.0 + dz.1 * dz.1;
+
+    // distance = (1 - |dz|^2) / |dcdz + dzdz * dc / (1 - dz)|,
+    // division is multiplication by the conjugate
+    let a = (dzdz.0 * dc.0 - dzdz.1 * dc.1, dzdz.0 * dc.1 + dzdz.1 * dc.0);
+    let b = (1.0 - dz.0, -dz.1);
+    let b2 = b.0 * b.0 + b.1 * b.1;
+
+    let denominator = (
+        dcdz.0 + (a.0 * b.0 + a.1 * b.1) / b2,
+        dcdz.1 + (a.1 * b.0 - a.0 * b.1) / b2,
+    );
+    let distance = (1.0 - multiplier2)
+        / (denominator.0 * denominator.0 + denominator.1 * denominator.1).sqrt();
+
+    (period, multiplier2.sqrt(), distance)
+}
+
+fn complex_mul<F: Floating>(a: (F, F), b: (F, F)) -> (F, F) {
+    (a.0 * b.0 - a.1 * b.1, a.0 * b.1 + a.1 * b.0)
+}
+
+/// Direction of z / dz, out of z and the derivative dz/dc at the escape.
+/// Level sets of the potential are seen as a surface, that's its normal, looking from above.
+/// https://www.math.univ-toulouse.fr/~cheritat/wiki-draw/index.php/Mandelbrot_set#Normal_map_effect
+pub fn normal(x: f64, y: f64, dx: f64, dy: f64) -> (f64, f64) {
+    // z / dz = z * conj(dz) / |dz|^2, only the direction matters
+    let (re, im) = (x * dx + y * dy, y * dx - x * dy);
+    let length = (re * re + im * im).sqrt();
+
+    (re / length, im / length)
+}
+
+/// Running sum of the average coloring terms along the orbit. The last term is kept apart,
+/// the average is blended between the one with it and the one without it, by how far
+/// past the bailout the orbit went, bands between iteration counts disappear.
+/// http://jussiharkonen.com/files/on_fractal_coloring_techniques%28lo-res%29.pdf
+struct OrbitAverage {
+    average: Average,
+    /// Exponent of z, 2 unless it is a Multibrot
+    degree: f64,
+    /// |c| bounds |z_n| from both sides, along with |z_n-1|^degree
+    c: f64,
+    /// Squared magnitude of the previous point
+    previous: f64,
+    sum: f64,
+    last: f64,
+    terms: u32,
+}
+
+impl OrbitAverage {
+    /// Starting at z0, c is the constant added every iteration
+    fn new(average: Average, (x, y): (f64, f64), (cx, cy): (f64, f64)) -> Self {
+        OrbitAverage {
+            average,
+            degree: 2.0,
+            c: cx.hypot(cy),
+            previous: x * x + y * y,
+            sum: 0.0,
+            last: 0.0,
+            terms: 0,
+        }
+    }
+
+    fn with_degree(mut self, degree: f64) -> Self {
+        self.degree = degree;
+
+        self
+    }
+
+    /// Next point of the orbit
+    fn add(&mut self, x: f64, y: f64) {
+        let term = match self.average {
+            Average::Stripe(density) => 0.5 * (density * y.atan2(x)).sin() + 0.5,
+            Average::TriangleInequality => {
+                // ||z^2| - |c|| <= |z^2 + c| <= |z^2| + |c|, bounds meet when z is 0, term is skipped
+                let power = self.previous.powf(self.degree / 2.0);
+                let low = (power - self.c).abs();
+                let high = power + self.c;
+                self.previous = x * x + y * y;
+
+                if high - low <= 0.0 {
+                    return;
+                }
+
+                (self.previous.sqrt() - low) / (high - low)
+            }
+        };
+
+        self.sum += term;
+        self.last = term;
+        self.terms += 1;
+    }
+
+    /// Smoothed average, of the orbit which escaped at z past the squared bailout
+    fn smooth(&self, x: f64, y: f64, bailout: f64) -> f64 {
+        if self.terms < 2 {
+            return self.sum;
+        }
+
+        let average = self.sum / self.terms as f64;
+        let without_last = (self.sum - self.last) / (self.terms - 1) as f64;
+
+        // 1 if the orbit just escaped, 0 if it got raised to the degree past the bailout,
+        // ln|z| / ln(bailout) is the same out of the squared magnitudes
+        let escaped = (x * x + y * y).ln() / bailout.ln();
+        let fraction = (1.0 - escaped.log(self.degree)).clamp(0.0, 1.0);
+
+        without_last + (average - without_last) * fraction
+    }
+}
+
+/// |a - b| < tolerance, two subtractions are cheaper than abs() of the wider types
+pub fn close_enough<F: Floating>(a: F, b: F, tolerance: F) -> bool {
+    a - b < tolerance && b - a < tolerance
+}
+
+/// Formula of the orbit, the shared kernel iterates it. Fractals differ only in that,
+/// periodicity checking and what colorings need out of the orbit is the same for all of them.
+pub trait Formula<F: Floating>: Sync {
+    /// Exponent of z, the smooth averages depend on it
+    fn degree(&self) -> f64 {
+        2.0
+    }
+
+    /// First point of the orbit and the constant c, out of the pixel.
+    /// Mandelbrot-like sets start at 0, with c at the pixel.
+    fn start(&self, x0: F, y0: F) -> ((F, F), (F, F)) {
+        ((F::from(0.0), F::from(0.0)), (x0, y0))
+    }
+
+    /// Next point of the orbit, squares of z are at hand anyway, for the bailout
+    fn step(&self, z: (F, F), squares: (F, F), c: (F, F)) -> (F, F);
+
+    /// Derivative at the first point of the orbit, dz/dc is 0 for Mandelbrot-like sets
+    fn start_derivative(&self) -> (F, F) {
+        (F::from(0.0), F::from(0.0))
+    }
+
+    /// Next derivative, out of the current z. None if the formula is not complex differentiable,
+    /// there is no distance estimate then.
+    fn derivative(&self, _z: (F, F), _dz: (F, F)) -> Option<(F, F)> {
+        None
+    }
+
+    /// Points known to never escape, without iterating them
+    fn inside(&self, _c: (F, F)) -> bool {
+        false
+    }
+
+    /// Period, multiplier and distance to the boundary, of the cycle the orbit fell into
+    fn interior(&self, _z: (F, F), _c: (F, F), _period: u32) -> Option<(u32, f64, f64)> {
+        None
+    }
+}
+
+/// Iterates the formula for every pixel of the area, gives what the coloring needs out of the orbit
+pub fn draw_orbits<F: Floating>(
+    context: &Context<F>,
+    area: &Area,
+    pixels: &mut [PixelResult],
+    formula: &impl Formula<F>,
+) -> Statistics {
+    let mut stats = Statistics::default();
+
+    let imgx = context.img_width as f64;
+    let imgy = context.img_height as f64;
+    let pinhole_center = context.poi.pinhole_size * F::from(0.5);
+
+    let center_x = context.poi.origin_x - pinhole_center;
+    let center_y = context.poi.origin_y - pinhole_center;
+
+    let tolerance = F::from(PERIODICITY_EPSILONS) * F::epsilon();
+
+    let derivative = context.coloring.needs_derivative();
+    let average = context.coloring.average();
+    let escape_point = context.coloring.needs_escape_point();
+    let escape = bailout(context.coloring.needs_far_bailout());
+    let bailout = F::from(escape);
+    // Shortcut does not find the cycle, interior has to be iterated then
+    let cycle = context.interior.needs_cycle();
+    let trap = context.coloring.trap();
+
+    //TODO: range span?? calc min and max
+    for pixel_y in 0..area.height {
+        let y_offset = (area.y + pixel_y * area.step) as f64;
+        let y0 = F::from(y_offset / imgy) * context.poi.pinhole_size + center_y;
+
+        // TODO: this repeats every row, store value in an array?
+        for pixel_x in 0..area.width {
+            let x_offset = (area.x + pixel_x * area.step) as f64;
+            let x0 = F::from(x_offset / imgx) * context.poi.pinhole_size + center_x;
+
+            if !cycle && formula.inside((x0, y0)) {
+                stats.interior_shortcut += 1;
+                pixels[(pixel_y * area.width + pixel_x) as usize] = PixelResult {
+                    iteration: context.poi.limit,
+                    ..Default::default()
+                };
+                continue;
+            }
+
+            let ((mut x, mut y), c) = formula.start(x0, y0);
+            let mut iteration = 0;
+
+            let mut x2 = x * x;
+            let mut y2 = y * y;
+            let mut sum = x2 + y2;
+
+            // Brent's cycle detection: orbit is compared with the point saved at
+            // the beginning of a window, window length doubles every time.
+            // If they meet, orbit is periodic, and will never escape.
+            let mut check_x = x;
+            let mut check_y = y;
+            let mut check_step = 0;
+            let mut check_window = 1;
+            let mut period = 0;
+
+            let mut dz = if derivative {
+                Some(formula.start_derivative())
+            } else {
+                None
+            };
+
+            let mut trap_distance = f64::INFINITY;
+            let mut trap_iteration = 0;
+            let mut orbit = average.map(|average| {
+                OrbitAverage::new(average, (x.into(), y.into()), (c.0.into(), c.1.into()))
+                    .with_degree(formula.degree())
+            });
+
+            while sum < bailout && iteration < context.poi.limit {
+                if let Some(previous) = dz {
+                    dz = formula.derivative((x, y), previous);
+                }
+
+                let next = formula.step((x, y), (x2, y2), c);
+                x = next.0;
+                y = next.1;
+
+                x2 = x * x;
+                y2 = y * y;
+                sum = x2 + y2;
+
+                iteration += 1;
+
+                if let Some(trap) = trap {
+                    let distance = trap.distance(x.into(), y.into());
+                    if distance < trap_distance {
+                        trap_distance = distance;
+                        trap_iteration = iteration;
+                    }
+                }
+
+                if let Some(orbit) = &mut orbit {
+                    orbit.add(x.into(), y.into());
+                }
+
+                check_step += 1;
+                if close_enough(x, check_x, tolerance) && close_enough(y, check_y, tolerance) {
+                    period = check_step;
+                    iteration = context.poi.limit;
+                    stats.periodic += 1;
+                    break;
+                }
+
+                if check_step == check_window {
+                    check_step = 0;
+                    check_window *= 2;
+                    check_x = x;
+                    check_y = y;
+                }
+            }
+
+            let mut result = PixelResult {
+                iteration,
+                period,
+                ..Default::default()
+            };
+            let escaped = iteration < context.poi.limit;
+
+            if trap.is_some() {
+                result.trap_distance = trap_distance;
+                result.trap_iteration = trap_iteration;
+            }
+
+            if let Some(orbit) = &orbit {
+                if escaped {
+                    result.average = orbit.smooth(x.into(), y.into(), escape);
+                }
+            }
+
+            if escape_point && escaped {
+                result.escape = (x.into(), y.into());
+            }
+
+            if cycle && period != 0 {
+                if let Some((period, multiplier, distance)) = formula.interior((x, y), c, period) {
+                    result.period = period;
+                    result.multiplier = multiplier;
+                    result.distance = distance;
+                }
+            } else if let Some((dx, dy)) = dz.filter(|_| escaped) {
+                // Estimate does not depend on the degree, it is the Koebe quarter theorem
+                let (x, y, dx, dy) = (x.into(), y.into(), dx.into(), dy.into());
+                result.distance = distance_estimate(x * x + y * y, dx * dx + dy * dy);
+                result.normal = normal(x, y, dx, dy);
+            }
+
+            pixels[(pixel_y * area.width + pixel_x) as usize] = result;
+        }
+    }
+
+    stats
 }
 
 // Unused type parameters cause some internal compiler problems
@@ -51,55 +607,164 @@ pub trait FractalFunction<F: Floating>: Send + Sync {
 // making compiler happy.
 pub struct Mandelbrot<F>(pub PhantomData<F>);
 
+impl<F: Floating> Formula<F> for Mandelbrot<F> {
+    fn step(&self, (x, y): (F, F), (x2, y2): (F, F), (x0, y0): (F, F)) -> (F, F) {
+        (x2 - y2 + x0, (x + x) * y + y0)
+    }
+
+    /// dz = 2 * z * dz + 1
+    fn derivative(&self, (x, y): (F, F), (dx, dy): (F, F)) -> Option<(F, F)> {
+        let two = F::from(2.0);
+
+        Some((
+            two * (x * dx - y * dy) + F::from(1.0),
+            two * (x * dy + y * dx),
+        ))
+    }
+
+    fn inside(&self, (x0, y0): (F, F)) -> bool {
+        in_cardioid_or_bulb(x0, y0)
+    }
+
+    fn interior(&self, (x, y): (F, F), (x0, y0): (F, F), period: u32) -> Option<(u32, f64, f64)> {
+        Some(interior_estimate(x, y, x0, y0, period))
+    }
+}
+
 impl<F: Floating> FractalFunction<F> for Mandelbrot<F> {
-    fn draw(&self, context: &Context<F>, id: u32, height: u32, pixels: &mut [Rgb<u8>]) {
-        let imgx = context.img_width as f64;
-        let imgy = context.img_height as f64;
-        let pinhole_center = context.poi.pinhole_size * F::from(0.5);
+    fn draw(&self, context: &Context<F>, area: &Area, pixels: &mut [PixelResult]) -> Statistics {
+        draw_orbits(context, area, pixels, self)
+    }
+}
 
-        let center_x = context.poi.origin_x - pinhole_center;
-        let center_y = context.poi.origin_y - pinhole_center;
+/// Filled Julia set of z^2 + c, c is fixed, the pixel is where the orbit starts.
+/// Same as the Mandelbrot, but interior is not estimated, the multiplier of the cycle is the same
+/// for all of it, and there are no shortcuts.
+pub struct Julia<F> {
+    pub x: F,
+    pub y: F,
+}
 
-        let four: F = F::from(4.0);
+impl<F: Floating> Formula<F> for Julia<F> {
+    fn start(&self, x0: F, y0: F) -> ((F, F), (F, F)) {
+        ((x0, y0), (self.x, self.y))
+    }
 
-        //TODO: range span?? calc min and max
-        for pixel_y in 0..height {
-            let y_offset = (pixel_y + id * height) as f64;
-            let y0 = F::from(y_offset / imgy) * context.poi.pinhole_size + center_y;
+    fn step(&self, z: (F, F), squares: (F, F), c: (F, F)) -> (F, F) {
+        Mandelbrot(PhantomData).step(z, squares, c)
+    }
 
-            // TODO: this repeats every row, store value in an array?
-            for pixel_x in 0..context.img_width {
-                let x0 = F::from(pixel_x as f64 / imgx) * context.poi.pinhole_size + center_x;
+    /// Derivative dz/dz0 starts at 1
+    fn start_derivative(&self) -> (F, F) {
+        (F::from(1.0), F::from(0.0))
+    }
 
-                let mut x = F::from(0.0);
-                let mut y = F::from(0.0);
-                let mut iteration = 0;
+    /// dz = 2 * z * dz
+    fn derivative(&self, (x, y): (F, F), (dx, dy): (F, F)) -> Option<(F, F)> {
+        let two = F::from(2.0);
 
-                let mut x2 = F::from(0.0);
-                let mut y2 = F::from(0.0);
-                let mut sum = F::from(0.0);
+        Some((two * (x * dx - y * dy), two * (x * dy + y * dx)))
+    }
+}
 
-                while sum < four && iteration < context.poi.limit {
-                    y = (x + x) * y + y0;
+impl<F: Floating> FractalFunction<F> for Julia<F> {
+    fn draw(&self, context: &Context<F>, area: &Area, pixels: &mut [PixelResult]) -> Statistics {
+        draw_orbits(context, area, pixels, self)
+    }
+}
 
-                    x = x2 - y2 + x0;
+/// Burning Ship, z = (|Re z| + i|Im z|)^2 + c. Folding z to the first quadrant is not complex
+/// differentiable, so there is no derivative, and no distance estimates of either side,
+/// nor shortcuts, periodicity checking still applies.
+pub struct BurningShip<F>(pub PhantomData<F>);
 
-                    x2 = x * x;
+impl<F: Floating> Formula<F> for BurningShip<F> {
+    fn step(&self, (x, y): (F, F), (x2, y2): (F, F), (x0, y0): (F, F)) -> (F, F) {
+        let (ax, ay) = (x.abs(), y.abs());
 
-                    y2 = y * y;
+        (x2 - y2 + x0, (ax + ax) * ay + y0)
+    }
+}
 
-                    sum = x2 + y2;
+impl<F: Floating> FractalFunction<F> for BurningShip<F> {
+    fn draw(&self, context: &Context<F>, area: &Area, pixels: &mut [PixelResult]) -> Statistics {
+        draw_orbits(context, area, pixels, self)
+    }
+}
 
-                    iteration += 1;
-                }
+/// Multibrot set of z^power + c, iterated by repeated complex multiplication.
+/// Square of it is the Mandelbrot set, to the last bit, with its shortcuts and interior estimates.
+pub struct Multibrot<F> {
+    power: u32,
+    _marker: PhantomData<F>,
+}
 
-                pixels[(pixel_y * context.img_height + pixel_x) as usize] =
-                    color_rainbow(iteration, context.poi.limit);
-            }
+impl<F: Floating> Multibrot<F> {
+    /// Powers below 2 are taken as 2, the Mandelbrot set
+    pub fn new(power: u32) -> Self {
+        Multibrot {
+            power: power.max(2),
+            _marker: PhantomData,
+        }
+    }
+
+    /// z^(power - 1), the step multiplies it by z once more, the derivative needs it as it is
+    fn power_below(&self, (x, y): (F, F)) -> (F, F) {
+        let (mut px, mut py) = (x, y);
+
+        for _ in 2..self.power {
+            let px_next = px * x - py * y;
+            py = px * y + py * x;
+            px = px_next;
+        }
+
+        (px, py)
+    }
+}
+
+impl<F: Floating> Formula<F> for Multibrot<F> {
+    fn degree(&self) -> f64 {
+        self.power as f64
+    }
+
+    fn step(&self, (x, y): (F, F), _squares: (F, F), (x0, y0): (F, F)) -> (F, F) {
+        let (px, py) = self.power_below((x, y));
+
+        (px * x - py * y + x0, px * y + py * x + y0)
+    }
+
+    /// dz = power * z^(power - 1) * dz + 1
+    fn derivative(&self, z: (F, F), (dx, dy): (F, F)) -> Option<(F, F)> {
+        let (px, py) = self.power_below(z);
+        let power = F::from(self.power as f64);
+        let (gx, gy) = (power * px, power * py);
+
+        Some((gx * dx - gy * dy + F::from(1.0), gx * dy + gy * dx))
+    }
+
+    fn inside(&self, c: (F, F)) -> bool {
+        self.power == 2 && Mandelbrot(PhantomData).inside(c)
+    }
+
+    fn interior(&self, z: (F, F), c: (F, F), period: u32) -> Option<(u32, f64, f64)> {
+        if self.power == 2 {
+            Mandelbrot(PhantomData).interior(z, c, period)
+        } else {
+            None
         }
     }
 }
 
+impl<F: Floating> FractalFunction<F> for Multibrot<F> {
+    fn draw(&self, context: &Context<F>, area: &Area, pixels: &mut [PixelResult]) -> Statistics {
+        draw_orbits(context, area, pixels, self)
+    }
+
+    fn step_power(&mut self, steps: i32) {
+        self.power = (self.power as i32 + steps).max(2) as u32;
+    }
+}
+
 // TODO: extract to be a strategy
 pub fn color_rainbow(iteration: u32, limit: u32) -> image::Rgb<u8> {
     // TODO: variable names are nonsense, refactor
@@ -125,3 +790,334 @@ pub fn color_rainbow(iteration: u32, limit: u32) -> image::Rgb<u8> {
 
     pixel
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        coloring::{Coloring, Interior},
+        fractal_exotic::{BurningShipAvx2, MandelbrotAvx2, MandelbrotAvx512, MultibrotReal},
+        quadruple::Quad,
+        supersampling::Sampling,
+    };
+
+    #[test]
+    fn cardioid_and_bulb_detection_works() {
+        for (x, y) in [
+            (0.0, 0.0),
+            (-0.5, 0.5),
+            (0.2, 0.0),
+            (-1.0, 0.0),
+            (-1.2, 0.1),
+        ] {
+            assert!(in_cardioid_or_bulb(x, y), "{} {}", x, y);
+        }
+
+        // Outside, or inside of other bulbs
+        for (x, y) in [
+            (0.3, 0.0),
+            (-0.75, 0.2),
+            (-1.3, 0.0),
+            (-2.0, 0.0),
+            (-0.1, 0.9),
+        ] {
+            assert!(!in_cardioid_or_bulb(x, y), "{} {}", x, y);
+        }
+    }
+
+    fn draw(poi: PoI<f64>, size: u32) -> (Vec<PixelResult>, Statistics) {
+        draw_with(&Mandelbrot(PhantomData), Coloring::Rainbow, poi, size)
+    }
+
+    fn draw_with<F: Floating>(
+        fractal: &dyn FractalFunction<F>,
+        coloring: Coloring,
+        poi: PoI<F>,
+        size: u32,
+    ) -> (Vec<PixelResult>, Statistics) {
+        let context = Context {
+            img_width: size,
+            img_height: size,
+            pinhole_step: F::from(1.0),
+            sampling: Sampling::Single,
+            coloring,
+            interior: Interior::Black,
+            palette: None,
+            poi,
+        };
+
+        let mut pixels = vec![PixelResult::default(); (size * size) as usize];
+        let area = Area {
+            x: 0,
+            y: 0,
+            width: size,
+            height: size,
+            step: 1,
+        };
+        let stats = fractal.draw(&context, &area, &mut pixels);
+
+        (pixels, stats)
+    }
+
+    #[test]
+    fn interior_pixels_are_counted() {
+        let limit = 300;
+        let (pixels, stats) = draw(
+            PoI {
+                origin_x: -0.5,
+                origin_y: 0.0,
+                pinhole_size: 3.0,
+                limit,
+            },
+            64,
+        );
+
+        let inside = pixels.iter().filter(|p| p.iteration == limit).count() as u32;
+
+        assert!(stats.interior_shortcut > 0);
+        // Rest of the inside is in the smaller bulbs, cycle detection takes care of those.
+        // Few points close to the boundary converge too slow to be caught before the limit.
+        let detected = stats.interior_shortcut + stats.periodic;
+        assert!(stats.periodic > 0);
+        assert!(detected <= inside);
+        assert!(detected as f64 > 0.9 * inside as f64);
+    }
+
+    #[test]
+    fn simd_counts_only_skipped_vectors() {
+        // Row along the real axis from -1.2 by 0.1, inside of the bulb and the cardioid up to 0.2
+        let context = Context {
+            img_width: 20,
+            img_height: 1,
+            pinhole_step: 1.0,
+            sampling: Sampling::Single,
+            coloring: Coloring::Rainbow,
+            interior: Interior::Black,
+            palette: None,
+            poi: PoI {
+                origin_x: -0.2,
+                origin_y: 1.0,
+                pinhole_size: 2.0,
+                limit: 300,
+            },
+        };
+        let area = Area {
+            x: 0,
+            y: 0,
+            width: 20,
+            height: 1,
+            step: 1,
+        };
+        let count = |fractal: &dyn FractalFunction<f64>| {
+            let mut pixels = vec![PixelResult::default(); 20];
+            fractal.draw(&context, &area, &mut pixels).interior_shortcut
+        };
+
+        assert_eq!(count(&Mandelbrot(PhantomData)), 15);
+
+        // Vector mixing interior and escaping lanes is iterated as a whole
+        if is_x86_feature_detected!("avx2") {
+            assert_eq!(count(&MandelbrotAvx2), 12);
+        }
+
+        if is_x86_feature_detected!("avx512f") {
+            assert_eq!(count(&MandelbrotAvx512), 8);
+        }
+    }
+
+    #[test]
+    fn period_is_detected() {
+        // Single pixel in the middle of period-3, period-4 and period-5 bulbs
+        for (x, y, period) in [
+            (-0.1225, 0.7449, 3),
+            (-1.3107, 0.0, 4),
+            (-0.5043, 0.5627, 5),
+        ] {
+            let (pixels, stats) = draw(
+                PoI {
+                    origin_x: x,
+                    origin_y: y,
+                    pinhole_size: 0.0,
+                    limit: 10000,
+                },
+                1,
+            );
+
+            assert_eq!(pixels[0].iteration, 10000);
+            assert_eq!(pixels[0].period, period);
+            assert_eq!(stats.periodic, 1);
+        }
+
+        // Escapes, never periodic
+        let (pixels, stats) = draw(
+            PoI {
+                origin_x: 0.3,
+                origin_y: 0.0,
+                pinhole_size: 0.0,
+                limit: 10000,
+            },
+            1,
+        );
+
+        assert!(pixels[0].iteration < 10000);
+        assert_eq!(pixels[0].period, 0);
+        assert_eq!(stats.periodic, 0);
+    }
+
+    fn ship<F: Floating>() -> PoI<F> {
+        PoI {
+            origin_x: F::from(-0.5),
+            origin_y: F::from(-0.5),
+            pinhole_size: F::from(4.0),
+            limit: 300,
+        }
+    }
+
+    #[test]
+    fn burning_ship_folds_the_orbit() {
+        // Real axis never gets folded, it is the same as the Mandelbrot there
+        let real = |fractal: &dyn FractalFunction<f64>| {
+            let poi = PoI {
+                origin_x: -1.9,
+                origin_y: 0.0,
+                pinhole_size: 0.0,
+                limit: 300,
+            };
+            draw_with(fractal, Coloring::Rainbow, poi, 1).0[0]
+        };
+        assert_eq!(
+            real(&BurningShip(PhantomData)),
+            real(&Mandelbrot(PhantomData))
+        );
+
+        // Unlike the Mandelbrot set, it is not symmetric across the real axis
+        let (pixels, stats) = draw_with(
+            &BurningShip::<f64>(PhantomData),
+            Coloring::Rainbow,
+            ship(),
+            64,
+        );
+        let mirrored = (1..64).any(|y| {
+            (0..64).any(|x| pixels[y * 64 + x].iteration != pixels[(64 - y) * 64 + x].iteration)
+        });
+        assert!(mirrored);
+        assert!(stats.periodic > 0);
+        assert_eq!(stats.interior_shortcut, 0);
+    }
+
+    #[test]
+    fn burning_ship_is_the_same_in_every_kernel() {
+        let (expected, _) = draw_with(
+            &BurningShip::<f64>(PhantomData),
+            Coloring::Decomposition,
+            ship(),
+            64,
+        );
+
+        // Orbits diverge in the last bits, only few pixels near the boundary may differ
+        let (quad, _) = draw_with(
+            &BurningShip::<Quad>(PhantomData),
+            Coloring::Decomposition,
+            ship(),
+            64,
+        );
+        let same = quad
+            .iter()
+            .zip(expected.iter())
+            .filter(|(quad, expected)| quad.iteration == expected.iteration)
+            .count();
+        assert!(same as f64 > 0.99 * expected.len() as f64);
+
+        if is_x86_feature_detected!("avx2") {
+            let (avx2, _) = draw_with(&BurningShipAvx2, Coloring::Decomposition, ship(), 64);
+            assert!(avx2 == expected);
+
+            // Width not multiple of 4, last lanes are not stored
+            let (avx2, _) = draw_with(&BurningShipAvx2, Coloring::Rainbow, ship(), 7);
+            let (expected, _) = draw_with(
+                &BurningShip::<f64>(PhantomData),
+                Coloring::Rainbow,
+                ship(),
+                7,
+            );
+            assert!(avx2 == expected);
+        }
+    }
+
+    #[test]
+    fn multibrot_of_power_2_is_the_mandelbrot() {
+        let poi = || PoI {
+            origin_x: -0.5,
+            origin_y: 0.1,
+            pinhole_size: 3.0,
+            limit: 300,
+        };
+        let (mandelbrot, expected) =
+            draw_with(&Mandelbrot(PhantomData), Coloring::Distance(1.0), poi(), 32);
+        let (multibrot, stats) = draw_with(&Multibrot::new(2), Coloring::Distance(1.0), poi(), 32);
+
+        // Same path, shortcuts included
+        assert!(stats.interior_shortcut > 0);
+        assert_eq!(stats.interior_shortcut, expected.interior_shortcut);
+        assert!(multibrot == mandelbrot);
+    }
+
+    #[test]
+    fn real_power_matches_the_integer_one() {
+        let poi = || PoI {
+            origin_x: 0.0,
+            origin_y: 0.0,
+            pinhole_size: 3.0,
+            limit: 300,
+        };
+        let (integer, _) = draw_with(&Multibrot::new(3), Coloring::Rainbow, poi(), 64);
+        let (real, _) = draw_with(&MultibrotReal::new(3.0), Coloring::Rainbow, poi(), 64);
+
+        // Polar form rounds differently, only few pixels near the boundary may differ
+        let same = integer
+            .iter()
+            .zip(real.iter())
+            .filter(|(integer, real)| integer.iteration == real.iteration)
+            .count();
+        assert!(same as f64 > 0.99 * integer.len() as f64);
+
+        // Cubic set is symmetric across both axes, quadratic only across the real one
+        let row = |y: usize| &integer[y * 64..(y + 1) * 64];
+        assert!(row(10)
+            .iter()
+            .zip(row(54).iter())
+            .all(|(a, b)| a.iteration == b.iteration));
+    }
+
+    #[test]
+    fn power_steps_are_clamped() {
+        let poi = || PoI {
+            origin_x: 0.0,
+            origin_y: 0.0,
+            pinhole_size: 3.0,
+            limit: 300,
+        };
+
+        let mut multibrot = Multibrot::<f64>::new(3);
+        multibrot.step_power(-5);
+        assert_eq!(multibrot.power, 2);
+        multibrot.step_power(2);
+        assert_eq!(multibrot.power, 4);
+
+        // Ten steps of a tenth land on the integer power exactly
+        let mut real = MultibrotReal::new(2.0);
+        for _ in 0..10 {
+            FractalFunction::<f64>::step_power(&mut real, 1);
+        }
+        let (moved, _) = draw_with(&real, Coloring::Rainbow, poi(), 16);
+        let (expected, _) = draw_with(&MultibrotReal::new(3.0), Coloring::Rainbow, poi(), 16);
+        assert_eq!(moved, expected);
+
+        // z + c escapes everywhere, the lowest power is a step above it
+        FractalFunction::<f64>::step_power(&mut real, -100);
+        let (lowest, _) = draw_with(&real, Coloring::Rainbow, poi(), 16);
+        let (expected, _) = draw_with(&MultibrotReal::new(1.0), Coloring::Rainbow, poi(), 16);
+        assert_eq!(lowest, expected);
+        assert_ne!(lowest, moved);
+    }
+}
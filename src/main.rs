@@ -1,11 +1,8 @@
 #![feature(trait_alias)]
 #![feature(stdsimd)]
+#![feature(test)]
 
-use std::marker::PhantomData;
-
-use fractal_exotic::{MandelbrotAvx2, MandelbrotRug};
-use fractals::Mandelbrot;
-use quadruple::Quad;
+use fractal_exotic::MandelbrotAvx2;
 
 use crate::{executor::ExecutorKind, fractal_builder::Fractal, fractals::PoI};
 
@@ -15,36 +12,24 @@ extern crate glium;
 #[macro_use]
 extern crate lazy_static;
 
+mod big_float;
+mod coloring;
 mod executor;
+mod fixed_point;
 mod fractal_builder;
 mod fractal_exotic;
 mod fractals;
+mod mariani_silver;
 mod opengl;
+mod palette;
 mod pipe;
+mod poster;
 mod quadruple;
 mod soft_float;
+mod supersampling;
 
 fn main() {
-    let mut fractal =
-            // Fractal::<Quad>::default()
-            // .with(Box::new(Mandelbrot(PhantomData)))
-            // .with_poi(PoI {
-            //     // Limit of Quad, yay!
-            //     origin_x: Quad {
-            //         lo: 0.000000000000000010150844351198857,
-            //         hi: -1.275160031112145,
-            //     },
-            //     origin_y: Quad {
-            //         lo: 0.000000000000000006705298387715869,
-            //         hi: -0.19410769865119987,
-            //     },
-            //     pinhole_size: Quad {
-            //         lo: 0.00000000000000000000000000000000000000000000002084738436862751,
-            //         hi: 0.0000000000000000000000000000004272805497045327,
-            //     },
-            //     limit: 1400,
-            // })
-        Fractal::<f64>::default()
+    let mut fractal = Fractal::<f64>::default()
         .with(Box::new(MandelbrotAvx2))
         .with_poi(PoI {
             // Template type deduction!
@@ -0,0 +1,378 @@
+///! Mariani-Silver algorithm, the set is connected, so if the whole border of a rectangle
+///! has the same value, so does the inside of it. Border is drawn, inside is filled if
+///! uniform, otherwise the rectangle is split into four and so on.
+///! https://en.wikibooks.org/wiki/Fractals/Iterations_in_the_complex_plane/Mandelbrot_set/mariani
+///! Works with any FractalFunction, it only decides which areas to draw.
+///! Thin filaments can slip between border pixels, that's the price.
+///! Only the iteration count and the period are filled, colorings needing floats measured for every
+///! pixel never see a uniform border, all of their pixels are drawn.
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use rayon::{
+    iter::{IndexedParallelIterator, ParallelIterator},
+    slice::ParallelSliceMut,
+};
+
+use crate::{
+    fractal_builder::Context,
+    fractals::{Area, Floating, FractalFunction, PixelResult, Statistics},
+};
+
+/// Image is split into tiles of that size, every tile is subdivided on its own
+const TILE_SIZE: u32 = 64;
+
+/// Rectangles this small are not worth subdividing anymore, inside is just drawn
+const MIN_SIZE: u32 = 8;
+
+/// Band of TILE_SIZE rows, handed to a single thread
+struct Band<'a> {
+    pixels: &'a mut [PixelResult],
+    y: u32,
+    width: u32,
+}
+
+impl<'a> Band<'a> {
+    fn index(&self, x: u32, y: u32) -> usize {
+        ((y - self.y) * self.width + x) as usize
+    }
+
+    fn get(&self, x: u32, y: u32) -> PixelResult {
+        self.pixels[self.index(x, y)]
+    }
+
+    fn set(&mut self, x: u32, y: u32, pixel: PixelResult) {
+        let index = self.index(x, y);
+        self.pixels[index] = pixel;
+    }
+}
+
+/// If the coloring looks only at the iteration count and the period, those are the same inside
+/// a uniform border. Distances, traps, averages and escape points are different for every pixel.
+fn fillable<F>(context: &Context<F>) -> bool {
+    !context.coloring.needs_far_bailout()
+        && !context.coloring.needs_orbit()
+        && !context.interior.needs_cycle()
+}
+
+fn same(a: PixelResult, b: PixelResult) -> bool {
+    a.iteration == b.iteration && a.period == b.period
+}
+
+/// Stops taking new tiles, when cancel is set, leaving the image unfinished
+pub fn render<F: Floating>(
+    context: &Context<F>,
+    fractal: &dyn FractalFunction<F>,
+    pixels: &mut [PixelResult],
+    cancel: &AtomicBool,
+) -> Statistics {
+    let fill = fillable(context);
+
+    pixels
+        .par_chunks_mut((TILE_SIZE * context.img_width) as usize)
+        .enumerate()
+        .map(|(id, chunk)| {
+            let mut band = Band {
+                y: id as u32 * TILE_SIZE,
+                width: context.img_width,
+                pixels: chunk,
+            };
+            let height = band.pixels.len() as u32 / band.width;
+
+            let mut stats = Statistics::default();
+            for x in (0..context.img_width).step_by(TILE_SIZE as usize) {
+                if cancel.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                let tile = Area {
+                    x,
+                    y: band.y,
+                    width: (context.img_width - x).min(TILE_SIZE),
+                    height,
+                    step: 1,
+                };
+
+                stats = stats
+                    + if fill {
+                        render_tile(context, fractal, &mut band, tile)
+                    } else {
+                        draw(context, fractal, &mut band, tile)
+                    };
+            }
+
+            stats
+        })
+        .reduce(Statistics::default, |a, b| a + b)
+}
+
+fn render_tile<F: Floating>(
+    context: &Context<F>,
+    fractal: &dyn FractalFunction<F>,
+    band: &mut Band,
+    tile: Area,
+) -> Statistics {
+    if tile.width <= MIN_SIZE || tile.height <= MIN_SIZE {
+        return draw(context, fractal, band, tile);
+    }
+
+    let right = tile.x + tile.width - 1;
+    let bottom = tile.y + tile.height - 1;
+
+    let mut stats = Statistics::default();
+    for edge in [
+        Area { height: 1, ..tile },
+        Area {
+            y: bottom,
+            height: 1,
+            ..tile
+        },
+        Area {
+            y: tile.y + 1,
+            width: 1,
+            height: tile.height - 2,
+            ..tile
+        },
+        Area {
+            x: right,
+            y: tile.y + 1,
+            width: 1,
+            height: tile.height - 2,
+            step: 1,
+        },
+    ] {
+        stats = stats + draw(context, fractal, band, edge);
+    }
+
+    stats + subdivide(context, fractal, band, tile)
+}
+
+/// Border of the area has to be drawn already
+fn subdivide<F: Floating>(
+    context: &Context<F>,
+    fractal: &dyn FractalFunction<F>,
+    band: &mut Band,
+    area: Area,
+) -> Statistics {
+    let mut stats = Statistics::default();
+
+    if area.width <= 2 || area.height <= 2 {
+        return stats;
+    }
+
+    let right = area.x + area.width - 1;
+    let bottom = area.y + area.height - 1;
+    let inside = Area {
+        x: area.x + 1,
+        y: area.y + 1,
+        width: area.width - 2,
+        height: area.height - 2,
+        step: 1,
+    };
+
+    let first = band.get(area.x, area.y);
+    let uniform = (area.x..=right)
+        .all(|x| same(band.get(x, area.y), first) && same(band.get(x, bottom), first))
+        && (inside.y..bottom)
+            .all(|y| same(band.get(area.x, y), first) && same(band.get(right, y), first));
+
+    if uniform {
+        for y in inside.y..bottom {
+            for x in inside.x..right {
+                band.set(x, y, first);
+            }
+        }
+
+        stats.filled += inside.width * inside.height;
+        return stats;
+    }
+
+    if area.width <= MIN_SIZE || area.height <= MIN_SIZE {
+        return draw(context, fractal, band, inside);
+    }
+
+    // Split by a row and a column through the middle, those become borders of the quarters
+    let middle_x = area.x + area.width / 2;
+    let middle_y = area.y + area.height / 2;
+
+    for line in [
+        Area {
+            y: middle_y,
+            height: 1,
+            ..inside
+        },
+        Area {
+            x: middle_x,
+            width: 1,
+            height: middle_y - inside.y,
+            ..inside
+        },
+        Area {
+            x: middle_x,
+            y: middle_y + 1,
+            width: 1,
+            height: bottom - middle_y - 1,
+            step: 1,
+        },
+    ] {
+        stats = stats + draw(context, fractal, band, line);
+    }
+
+    let left_width = middle_x - area.x + 1;
+    let right_width = right - middle_x + 1;
+    let top_height = middle_y - area.y + 1;
+    let bottom_height = bottom - middle_y + 1;
+
+    for quarter in [
+        Area {
+            width: left_width,
+            height: top_height,
+            ..area
+        },
+        Area {
+            x: middle_x,
+            width: right_width,
+            height: top_height,
+            ..area
+        },
+        Area {
+            y: middle_y,
+            width: left_width,
+            height: bottom_height,
+            ..area
+        },
+        Area {
+            x: middle_x,
+            y: middle_y,
+            width: right_width,
+            height: bottom_height,
+            step: 1,
+        },
+    ] {
+        stats = stats + subdivide(context, fractal, band, quarter);
+    }
+
+    stats
+}
+
+/// Draws the area with the fractal function, and copies it to the band
+fn draw<F: Floating>(
+    context: &Context<F>,
+    fractal: &dyn FractalFunction<F>,
+    band: &mut Band,
+    area: Area,
+) -> Statistics {
+    if area.width == 0 || area.height == 0 {
+        return Statistics::default();
+    }
+
+    let mut pixels = vec![PixelResult::default(); (area.width * area.height) as usize];
+    let stats = fractal.draw(context, &area, &mut pixels);
+
+    for (row, y) in (area.y..area.y + area.height).enumerate() {
+        let start = band.index(area.x, y);
+        let width = area.width as usize;
+
+        band.pixels[start..start + width].copy_from_slice(&pixels[row * width..(row + 1) * width]);
+    }
+
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        coloring::{Coloring, Interior},
+        fractal_exotic::{MandelbrotAvx2, MandelbrotAvx512},
+        fractals::{Mandelbrot, PoI},
+        supersampling::Sampling,
+    };
+    use std::marker::PhantomData;
+
+    fn context(width: u32, height: u32, limit: u32) -> Context<f64> {
+        with_coloring(width, height, limit, Coloring::Rainbow)
+    }
+
+    fn with_coloring(width: u32, height: u32, limit: u32, coloring: Coloring) -> Context<f64> {
+        Context {
+            img_width: width,
+            img_height: height,
+            pinhole_step: 1.0,
+            sampling: Sampling::Single,
+            coloring,
+            interior: Interior::Black,
+            palette: None,
+            poi: PoI {
+                origin_x: -0.5,
+                origin_y: 0.0,
+                pinhole_size: 3.0,
+                limit,
+            },
+        }
+    }
+
+    /// Returns how many pixels differ from drawing every one of them
+    fn compare(context: &Context<f64>, fractal: &dyn FractalFunction<f64>) -> (usize, Statistics) {
+        let whole = Area {
+            x: 0,
+            y: 0,
+            width: context.img_width,
+            height: context.img_height,
+            step: 1,
+        };
+        let mut expected = vec![PixelResult::default(); (whole.width * whole.height) as usize];
+        fractal.draw(context, &whole, &mut expected);
+
+        let mut pixels = vec![PixelResult::default(); expected.len()];
+        let stats = render(context, fractal, &mut pixels, &AtomicBool::new(false));
+
+        let diff = pixels
+            .iter()
+            .zip(expected.iter())
+            .filter(|(a, b)| a != b)
+            .count();
+
+        (diff, stats)
+    }
+
+    #[test]
+    fn matches_drawing_every_pixel() {
+        let context = context(256, 256, 300);
+        let (diff, stats) = compare(&context, &Mandelbrot::<f64>(PhantomData));
+
+        // Inside of the set is uniform, large part of the image does not need iterating
+        assert!(stats.filled > 256 * 256 / 10, "{:?}", stats);
+        assert!(diff < 256 * 256 / 1000, "{} {:?}", diff, stats);
+    }
+
+    #[test]
+    fn works_with_any_size_and_simd() {
+        // Not multiple of tile size, nor of SIMD width
+        let context = context(203, 131, 300);
+
+        let (diff, _) = compare(&context, &Mandelbrot::<f64>(PhantomData));
+        assert!(diff < 203 * 131 / 1000, "{}", diff);
+
+        if is_x86_feature_detected!("avx2") {
+            let (diff, _) = compare(&context, &MandelbrotAvx2);
+            assert!(diff < 203 * 131 / 1000, "{}", diff);
+        }
+
+        if is_x86_feature_detected!("avx512f") {
+            let (diff, _) = compare(&context, &MandelbrotAvx512);
+            assert!(diff < 203 * 131 / 1000, "{}", diff);
+        }
+    }
+
+    #[test]
+    fn fills_only_iteration_colorings() {
+        let (_, stats) = compare(&context(128, 128, 300), &Mandelbrot::<f64>(PhantomData));
+        assert!(stats.filled > 0, "{:?}", stats);
+
+        // Distance is different for every pixel, filling would smear it
+        let context = with_coloring(128, 128, 300, Coloring::Distance(1.0));
+        let (diff, stats) = compare(&context, &Mandelbrot::<f64>(PhantomData));
+        assert_eq!(stats.filled, 0);
+        assert_eq!(diff, 0);
+    }
+}
@@ -1,11 +1,15 @@
 use crate::executor::{Command, FineDirection};
-use crate::pipe::{OutBuffer, Pipe};
+use crate::pipe::{Frame, Pipe};
+use glium::backend::Facade;
 use glium::glutin::dpi::PhysicalPosition;
 use glium::glutin::event::{ElementState, MouseButton, VirtualKeyCode};
 use glium::index::NoIndices;
+use glium::texture::{
+    ClientFormat, MipmapsOption, RawImage2d, Texture1d, Texture2d, UncompressedFloatFormat,
+};
+use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter, SamplerWrapFunction};
 use glium::{glutin, Surface, VertexBuffer};
-use glium::{glutin::dpi::LogicalSize, glutin::event_loop::EventLoop, Display};
-use std::sync::mpsc::Receiver;
+use glium::{glutin::dpi::LogicalSize, glutin::event_loop::EventLoop};
 
 #[derive(Copy, Clone)]
 struct Vertex {
@@ -19,25 +23,57 @@ struct Plane {
 }
 implement_vertex!(Vertex, position, tex_coords);
 
-fn get_texture(
-    display: &Display,
-    receiver: &Receiver<OutBuffer>,
-) -> Option<glium::texture::Texture2d> {
-    let fractal = match receiver.try_recv() {
-        Ok(buff) => buff,
-        Err(_) => return None,
-    };
+/// Palette cycling, the gradient is shifted every frame, orbits are not recomputed
+struct Cycling {
+    on: bool,
+    /// Gradient positions per frame, negative goes backwards
+    speed: f64,
+    offset: f64,
+}
+
+impl Default for Cycling {
+    fn default() -> Self {
+        Cycling {
+            on: false,
+            speed: 1.0,
+            offset: 0.0,
+        }
+    }
+}
 
-    let dimensions = fractal.dimensions();
+/// Frame on the GPU, gradient pixels are colored by the shader, so cycling costs nothing
+struct Textures {
+    /// Gradient positions, colors of pixels not on the gradient are packed in them
+    positions: Texture2d,
+    gradient: Texture1d,
+    /// Positions per repetition of the gradient
+    length: f32,
+}
 
-    let image = glium::texture::RawImage2d::from_raw_rgb(fractal.into_raw(), dimensions);
+fn get_textures(display: &impl Facade, frame: Frame) -> Textures {
+    let (gradient, length) = frame.gradient();
 
-    let texture = glium::texture::Texture2d::new(display, image).unwrap();
+    let positions = RawImage2d {
+        data: frame.positions.into(),
+        width: frame.width,
+        height: frame.height,
+        format: ClientFormat::F32,
+    };
 
-    Some(texture)
+    Textures {
+        positions: Texture2d::with_format(
+            display,
+            positions,
+            UncompressedFloatFormat::F32,
+            MipmapsOption::NoMipmap,
+        )
+        .unwrap(),
+        gradient: Texture1d::new(display, gradient).unwrap(),
+        length: length as f32,
+    }
 }
 
-fn create_plane(display: &Display) -> Plane {
+fn create_plane(display: &impl Facade) -> Plane {
     let shape = vec![
         Vertex {
             position: [-1.0, 1.0],
@@ -63,7 +99,7 @@ fn create_plane(display: &Display) -> Plane {
     }
 }
 
-fn create_program(display: &Display) -> glium::Program {
+fn create_program(display: &impl Facade) -> glium::Program {
     let vertex_shader_src = r#"
         #version 140
 
@@ -85,10 +121,27 @@ fn create_program(display: &Display) -> glium::Program {
         in vec2 v_tex_coords;
         out vec4 color;
 
-        uniform sampler2D tex;
+        uniform sampler2D positions;
+        uniform sampler1D gradient;
+        uniform float length;
+        uniform float offset;
 
         void main() {
-            color = texture(tex, v_tex_coords);
+            float position = texture(positions, v_tex_coords).r;
+
+            if (position < 0.0) {
+                // Color packed by pipe::pack, 8 bits per channel
+                float code = -1.0 - position;
+                vec3 rgb = vec3(
+                    floor(code / 65536.0),
+                    mod(floor(code / 256.0), 256.0),
+                    mod(code, 256.0));
+                color = vec4(rgb / 255.0, 1.0);
+            } else {
+                // Texel i holds the color at i, its center is half a texel further
+                float texel = 0.5 / float(textureSize(gradient, 0));
+                color = texture(gradient, fract((position + offset) / length) + texel);
+            }
         }
     "#;
 
@@ -98,6 +151,61 @@ fn create_program(display: &Display) -> glium::Program {
     program
 }
 
+/// Gradient pixels are looked up in the gradient texture, shifted by offset
+fn draw(
+    target: &mut impl Surface,
+    plane: &Plane,
+    program: &glium::Program,
+    textures: &Textures,
+    offset: f64,
+) {
+    let uniforms = uniform! {
+        matrix: [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [ 0.0, 0.0, 0.0, 1.0f32],
+        ],
+        // Positions in between pixels are meaningless, and packed colors would bleed into them
+        positions: textures
+            .positions
+            .sampled()
+            .magnify_filter(MagnifySamplerFilter::Nearest)
+            .minify_filter(MinifySamplerFilter::Nearest),
+        gradient: textures
+            .gradient
+            .sampled()
+            .wrap_function(SamplerWrapFunction::Repeat)
+            .magnify_filter(MagnifySamplerFilter::Linear)
+            .minify_filter(MinifySamplerFilter::Linear),
+        length: textures.length,
+        offset: offset as f32,
+    };
+
+    target
+        .draw(
+            &plane.vertex_buffer,
+            &plane.indices,
+            program,
+            &uniforms,
+            &Default::default(),
+        )
+        .unwrap();
+}
+
+/// Keys handled by the viewer itself, true if the image has to be recolored
+fn handle_cycling(key: VirtualKeyCode, cycling: &mut Cycling) -> bool {
+    match key {
+        VirtualKeyCode::C => cycling.on = !cycling.on,
+        VirtualKeyCode::Period => cycling.speed *= 2.0,
+        VirtualKeyCode::Comma => cycling.speed /= 2.0,
+        VirtualKeyCode::Slash => cycling.speed = -cycling.speed,
+        _ => return false,
+    }
+
+    true
+}
+
 fn handle_keyboard(key: VirtualKeyCode) -> Option<Command> {
     match key {
         VirtualKeyCode::LBracket => Some(Command::ZoomOut),
@@ -115,10 +223,14 @@ fn handle_keyboard(key: VirtualKeyCode) -> Option<Command> {
         VirtualKeyCode::Key8 => Some(Command::SetPOI(8)),
         VirtualKeyCode::Key9 => Some(Command::SetPOI(9)),
         VirtualKeyCode::Space => Some(Command::GetState),
+        VirtualKeyCode::Z => Some(Command::StartAutoZoom),
+        VirtualKeyCode::X => Some(Command::StopAutoZoom),
         VirtualKeyCode::Up => Some(Command::FineTune(FineDirection::Up)),
         VirtualKeyCode::Down => Some(Command::FineTune(FineDirection::Down)),
         VirtualKeyCode::Left => Some(Command::FineTune(FineDirection::Left)),
         VirtualKeyCode::Right => Some(Command::FineTune(FineDirection::Right)),
+        VirtualKeyCode::PageUp => Some(Command::MorePower),
+        VirtualKeyCode::PageDown => Some(Command::LessPower),
         _ => None,
     }
 }
@@ -142,6 +254,10 @@ pub fn run(pipe: Pipe) {
 
     let mut mouse_position = PhysicalPosition::new(0.0, 0.0);
 
+    let mut textures: Option<Textures> = None;
+    let mut cycling = Cycling::default();
+    let mut redraw = false;
+
     event_loop.run(move |event, _, control_flow| {
         match event {
             glutin::event::Event::WindowEvent { event, .. } => match event {
@@ -160,6 +276,8 @@ pub fn run(pipe: Pipe) {
                             if let Some(cmd) = handle_keyboard(key) {
                                 pipe.cmd_send.send(cmd).unwrap();
                             }
+
+                            redraw |= handle_cycling(key, &mut cycling);
                         }
                     }
                 }
@@ -203,29 +321,25 @@ pub fn run(pipe: Pipe) {
         let next_frame_time = std::time::Instant::now() + std::time::Duration::from_nanos(50_000);
         *control_flow = glutin::event_loop::ControlFlow::WaitUntil(next_frame_time);
 
-        if let Some(texture) = get_texture(&display, &pipe.img_rcv) {
+        if let Ok(frame) = pipe.img_rcv.try_recv() {
+            textures = Some(get_textures(&display, frame));
+            redraw = true;
+        }
+
+        if cycling.on {
+            cycling.offset += cycling.speed;
+        }
+
+        // Cycling redraws every frame, otherwise only a new or uncycled image is drawn
+        let offset = if cycling.on { cycling.offset } else { 0.0 };
+
+        if let Some(textures) = textures.as_ref().filter(|_| redraw || cycling.on) {
+            redraw = false;
+
             let mut target = display.draw();
             target.clear_color(0.0, 0.0, 1.0, 1.0);
 
-            let uniforms = uniform! {
-                matrix: [
-                    [1.0, 0.0, 0.0, 0.0],
-                    [0.0, 1.0, 0.0, 0.0],
-                    [0.0, 0.0, 1.0, 0.0],
-                    [ 0.0, 0.0, 0.0, 1.0f32],
-                ],
-                tex: &texture,
-            };
-
-            target
-                .draw(
-                    &plane.vertex_buffer,
-                    &plane.indices,
-                    &program,
-                    &uniforms,
-                    &Default::default(),
-                )
-                .unwrap();
+            draw(&mut target, &plane, &program, textures, offset);
             target.finish().unwrap();
         }
 
@@ -239,3 +353,47 @@ pub fn run(pipe: Pipe) {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fractals::color_rainbow, pipe::pack};
+    use glium::glutin::{dpi::PhysicalSize, platform::unix::EventLoopExtUnix};
+    use image::Rgb;
+
+    /// Needs a display, Mesa's software rasterizer will do, like Xvfb with LIBGL_ALWAYS_SOFTWARE=1
+    #[test]
+    #[ignore]
+    fn shader_colors_gradient_pixels() {
+        let event_loop = EventLoop::<()>::new_any_thread();
+        let context = glutin::ContextBuilder::new()
+            .build_headless(&event_loop, PhysicalSize::new(4, 1))
+            .unwrap();
+        let display = glium::HeadlessRenderer::new(context).unwrap();
+
+        let frame = Frame {
+            width: 4,
+            height: 1,
+            positions: vec![10.0, pack(Rgb([7, 8, 9])), 100.0, 350.0],
+            palette: None,
+        };
+        let textures = get_textures(&display, frame);
+        let target = Texture2d::empty(&display, 4, 1).unwrap();
+
+        let plane = create_plane(&display);
+        let program = create_program(&display);
+        draw(&mut target.as_surface(), &plane, &program, &textures, 20.0);
+
+        let pixels: Vec<Vec<(u8, u8, u8, u8)>> = target.read();
+        let rgb = |x: usize| {
+            let (r, g, b, _) = pixels[0][x];
+            Rgb([r, g, b])
+        };
+
+        assert_eq!(rgb(0), color_rainbow(30, u32::MAX));
+        assert_eq!(rgb(1), Rgb([7, 8, 9]));
+        assert_eq!(rgb(2), color_rainbow(120, u32::MAX));
+        // Gradient goes round
+        assert_eq!(rgb(3), color_rainbow(10, u32::MAX));
+    }
+}
@@ -0,0 +1,383 @@
+///! Gradients out of color stops, repeated along the iterations.
+///! Artists can supply them as files, Fractint .map or a simple text format,
+///! see Palette::load.
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use image::Rgb;
+
+use crate::supersampling::{to_linear, to_srgb};
+
+/// Iterations per one repetition of the gradient, unless told otherwise, same as the rainbow
+const DEFAULT_LENGTH: f64 = 360.0;
+
+/// Color space the colors between stops are blended in
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(dead_code)]
+pub enum Interpolation {
+    /// Straight, what Fractint does
+    Rgb,
+    /// Hue goes the shorter way round, saturated colors stay saturated
+    Hsv,
+    /// Perceptually uniform, no muddy or too bright middles
+    Oklab,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    /// Positions within 0..1 of the repetition, sorted, the last stop blends into the first one
+    stops: Vec<(f64, Rgb<u8>)>,
+    interpolation: Interpolation,
+    /// Iterations per one repetition of the gradient
+    length: f64,
+    /// Iterations the gradient is shifted by
+    offset: f64,
+}
+
+#[allow(dead_code)]
+impl Palette {
+    /// Stops are positions within 0..1 of the repetition and their colors, in any order
+    pub fn new(mut stops: Vec<(f64, Rgb<u8>)>, interpolation: Interpolation) -> Self {
+        assert!(!stops.is_empty(), "palette needs at least one stop");
+
+        for stop in &mut stops {
+            stop.0 = stop.0.rem_euclid(1.0);
+        }
+        stops.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        Palette {
+            stops,
+            interpolation,
+            length: DEFAULT_LENGTH,
+            offset: 0.0,
+        }
+    }
+
+    pub fn with_interpolation(mut self, interpolation: Interpolation) -> Self {
+        self.interpolation = interpolation;
+
+        self
+    }
+
+    /// Panics unless positive, colors would come out of division by zero or a negative
+    pub fn with_length(mut self, length: f64) -> Self {
+        assert!(length > 0.0, "palette length has to be positive");
+        self.length = length;
+
+        self
+    }
+
+    pub fn with_offset(mut self, offset: f64) -> Self {
+        self.offset = offset;
+
+        self
+    }
+
+    /// Iterations per one repetition of the gradient
+    pub fn length(&self) -> f64 {
+        self.length
+    }
+
+    /// Files with the .map extension are Fractint palettes, "r g b" per line, stops evenly spaced.
+    /// Any other is the text format, "position r g b" per line, position within 0..1.
+    /// Both skip empty lines, anything after the numbers, and the text format skips # comments.
+    /// Colors are blended in RGB, as Fractint does, change it with with_interpolation.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+
+        let stops = match path.extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("map") => parse_map(&text)?,
+            _ => parse_text(&text)?,
+        };
+
+        Ok(Palette::new(stops, Interpolation::Rgb))
+    }
+
+    /// Position is in iterations, may be fractional
+    pub fn color(&self, position: f64) -> Rgb<u8> {
+        let t = ((position + self.offset) / self.length).rem_euclid(1.0);
+
+        // Stops around t, the segment before the first stop wraps from the last one
+        let next = self.stops.iter().position(|stop| stop.0 > t);
+        let (from, to) = match next {
+            Some(0) | None => {
+                let (last, first) = (self.stops[self.stops.len() - 1], self.stops[0]);
+                if next.is_none() {
+                    (last, (first.0 + 1.0, first.1))
+                } else {
+                    ((last.0 - 1.0, last.1), first)
+                }
+            }
+            Some(i) => (self.stops[i - 1], self.stops[i]),
+        };
+
+        let span = to.0 - from.0;
+        let fraction = if span > 0.0 { (t - from.0) / span } else { 0.0 };
+
+        self.blend(from.1, to.1, fraction)
+    }
+
+    fn blend(&self, from: Rgb<u8>, to: Rgb<u8>, fraction: f64) -> Rgb<u8> {
+        let mix = |a: f64, b: f64| a + (b - a) * fraction;
+
+        match self.interpolation {
+            Interpolation::Rgb => {
+                let channel = |i: usize| mix(from.0[i] as f64, to.0[i] as f64).round() as u8;
+
+                Rgb([channel(0), channel(1), channel(2)])
+            }
+            Interpolation::Hsv => {
+                let (from, to) = (to_hsv(from), to_hsv(to));
+
+                // Shorter way round, grays have no hue and take the other one
+                let mut hue_from = if from.1 == 0.0 { to.0 } else { from.0 };
+                let hue_to = if to.1 == 0.0 { hue_from } else { to.0 };
+                if hue_to - hue_from > 180.0 {
+                    hue_from += 360.0;
+                } else if hue_from - hue_to > 180.0 {
+                    hue_from -= 360.0;
+                }
+
+                from_hsv(
+                    mix(hue_from, hue_to).rem_euclid(360.0),
+                    mix(from.1, to.1),
+                    mix(from.2, to.2),
+                )
+            }
+            Interpolation::Oklab => {
+                let (from, to) = (to_oklab(from), to_oklab(to));
+
+                from_oklab([
+                    mix(from[0], to[0]),
+                    mix(from[1], to[1]),
+                    mix(from[2], to[2]),
+                ])
+            }
+        }
+    }
+}
+
+fn parse_map(text: &str) -> io::Result<Vec<(f64, Rgb<u8>)>> {
+    let colors = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| parse_color(&mut line.split_whitespace(), line))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    if colors.is_empty() {
+        return Err(invalid("no colors"));
+    }
+
+    let count = colors.len() as f64;
+
+    Ok(colors
+        .into_iter()
+        .enumerate()
+        .map(|(i, color)| (i as f64 / count, color))
+        .collect())
+}
+
+fn parse_text(text: &str) -> io::Result<Vec<(f64, Rgb<u8>)>> {
+    let stops = text
+        .lines()
+        .map(|line| line.split('#').next().unwrap())
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut words = line.split_whitespace();
+            let position = words
+                .next()
+                .and_then(|word| word.parse::<f64>().ok())
+                .filter(|position| (0.0..=1.0).contains(position))
+                .ok_or_else(|| invalid(line))?;
+
+            Ok((position, parse_color(&mut words, line)?))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    if stops.is_empty() {
+        return Err(invalid("no color stops"));
+    }
+
+    Ok(stops)
+}
+
+/// Three numbers 0..255, words after them are comments
+fn parse_color<'a>(words: &mut impl Iterator<Item = &'a str>, line: &str) -> io::Result<Rgb<u8>> {
+    let mut channel = || {
+        words
+            .next()
+            .and_then(|word| word.parse::<u8>().ok())
+            .ok_or_else(|| invalid(line))
+    };
+
+    Ok(Rgb([channel()?, channel()?, channel()?]))
+}
+
+fn invalid(line: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("bad palette line: {}", line),
+    )
+}
+
+/// Hue in degrees, saturation and value within 0..1
+fn to_hsv(color: Rgb<u8>) -> (f64, f64, f64) {
+    let [r, g, b] = color.0.map(|channel| channel as f64 / 255.0);
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+
+    (hue, saturation, max)
+}
+
+fn from_hsv(hue: f64, saturation: f64, value: f64) -> Rgb<u8> {
+    let chroma = value * saturation;
+    let x = chroma * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
+
+    let (r, g, b) = match (hue / 60.0) as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let channel = |c: f64| ((c + value - chroma) * 255.0).round() as u8;
+
+    Rgb([channel(r), channel(g), channel(b)])
+}
+
+/// https://bottosson.github.io/posts/oklab/
+fn to_oklab(color: Rgb<u8>) -> [f64; 3] {
+    let [r, g, b] = color.0.map(to_linear);
+
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+fn from_oklab([lightness, a, b]: [f64; 3]) -> Rgb<u8> {
+    let l = (lightness + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m = (lightness - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s = (lightness - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+
+    Rgb([
+        to_srgb(4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s),
+        to_srgb(-1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s),
+        to_srgb(-0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: Rgb<u8> = Rgb([0, 0, 0]);
+    const WHITE: Rgb<u8> = Rgb([255, 255, 255]);
+    const RED: Rgb<u8> = Rgb([255, 0, 0]);
+    const BLUE: Rgb<u8> = Rgb([0, 0, 255]);
+
+    #[test]
+    fn gradient_repeats_and_wraps() {
+        let palette =
+            Palette::new(vec![(0.0, BLACK), (0.5, WHITE)], Interpolation::Rgb).with_length(100.0);
+
+        assert_eq!(palette.color(0.0), BLACK);
+        assert_eq!(palette.color(25.0), Rgb([128, 128, 128]));
+        assert_eq!(palette.color(50.0), WHITE);
+        // Last stop blends back into the first one
+        assert_eq!(palette.color(75.0), Rgb([128, 128, 128]));
+        assert_eq!(palette.color(150.0), WHITE);
+        assert_eq!(palette.with_offset(50.0).color(0.0), WHITE);
+    }
+
+    #[test]
+    fn interpolation_spaces_differ_in_the_middle() {
+        let middle = |interpolation| {
+            Palette::new(vec![(0.0, RED), (0.5, BLUE)], interpolation)
+                .with_length(2.0)
+                .color(0.5)
+        };
+
+        assert_eq!(middle(Interpolation::Rgb), Rgb([128, 0, 128]));
+        // Hue goes through magenta, at full saturation and value
+        assert_eq!(middle(Interpolation::Hsv), Rgb([255, 0, 255]));
+        // Lighter than the RGB one, with some green, which is too dark and too saturated
+        assert_eq!(middle(Interpolation::Oklab), Rgb([140, 83, 162]));
+
+        for interpolation in [Interpolation::Rgb, Interpolation::Hsv, Interpolation::Oklab] {
+            let palette = Palette::new(vec![(0.0, RED), (0.5, BLUE)], interpolation);
+            assert_eq!(palette.color(0.0), RED);
+            assert_eq!(palette.color(180.0), BLUE);
+        }
+    }
+
+    #[test]
+    fn fractint_map_is_parsed() {
+        let stops = parse_map("0 0 0 black, comment\n\n255 255 255\n0 0 255\n255 0 0").unwrap();
+
+        assert_eq!(
+            stops,
+            vec![(0.0, BLACK), (0.25, WHITE), (0.5, BLUE), (0.75, RED)]
+        );
+        assert!(parse_map("0 0").is_err());
+        assert!(parse_map("0 0 256").is_err());
+        assert!(parse_map("").is_err());
+        assert!(parse_map(" \n\t\n").is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_length_is_rejected() {
+        Palette::new(vec![(0.0, BLACK)], Interpolation::Rgb).with_length(0.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn nan_length_is_rejected() {
+        Palette::new(vec![(0.0, BLACK)], Interpolation::Rgb).with_length(f64::NAN);
+    }
+
+    #[test]
+    fn text_palette_is_parsed() {
+        let stops = parse_text("# sunset\n0.5 0 0 255\n\n0 255 0 0 # red\n").unwrap();
+
+        assert_eq!(stops, vec![(0.5, BLUE), (0.0, RED)]);
+        assert!(parse_text("1.5 0 0 0").is_err());
+        assert!(parse_text("# nothing").is_err());
+    }
+
+    #[test]
+    fn palette_is_loaded_by_extension() {
+        let dir = std::env::temp_dir();
+        let map = dir.join("fractal_palette_test.map");
+        let text = dir.join("fractal_palette_test.txt");
+        fs::write(&map, "255 0 0\n0 0 255\n").unwrap();
+        fs::write(&text, "0 255 0 0\n0.5 0 0 255\n").unwrap();
+
+        assert_eq!(Palette::load(&map).unwrap(), Palette::load(&text).unwrap());
+        assert!(Palette::load(&dir.join("fractal_palette_missing.map")).is_err());
+
+        fs::remove_file(map).unwrap();
+        fs::remove_file(text).unwrap();
+    }
+}
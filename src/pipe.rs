@@ -1,10 +1,95 @@
-use image::{ImageBuffer, Rgb};
-use std::sync::mpsc::{Receiver, Sender};
+use image::Rgb;
+use std::sync::{
+    mpsc::{Receiver, Sender},
+    Arc,
+};
 
-use crate::executor::Command;
+use crate::{coloring, executor::Command, palette::Palette};
 pub struct Pipe {
-    pub img_rcv: Receiver<OutBuffer>,
+    pub img_rcv: Receiver<Frame>,
     pub cmd_send: Sender<Command>,
 }
 
-pub type OutBuffer = ImageBuffer<Rgb<u8>, Vec<u8>>;
\ No newline at end of file
+/// Image, as what it was colored out of, so the viewer can recolor it without the executor.
+/// One f32 per pixel, only colors off the gradient are made here, the viewer makes the rest.
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    /// Position on the gradient of every pixel, or if its color does not come from it,
+    /// the color packed into a negative number by pack
+    pub positions: Vec<f32>,
+    /// Gradient the positions are on, rainbow if none
+    pub palette: Option<Arc<Palette>>,
+}
+
+/// Texels of the gradient texture, when there is a palette, the rainbow has one per degree
+const GRADIENT_TEXELS: usize = 1024;
+
+/// Color of a pixel off the gradient, as its position. All 24 bits are exact in f32.
+pub fn pack(Rgb([r, g, b]): Rgb<u8>) -> f32 {
+    -1.0 - ((r as u32) << 16 | (g as u32) << 8 | b as u32) as f32
+}
+
+impl Frame {
+    /// One repetition of the gradient, sampled for the texture the viewer looks positions up in,
+    /// and its length in positions
+    pub fn gradient(&self) -> (Vec<(u8, u8, u8)>, f64) {
+        let (texels, length) = match &self.palette {
+            Some(palette) => (GRADIENT_TEXELS, palette.length()),
+            None => (360, 360.0),
+        };
+
+        let colors = (0..texels)
+            .map(|i| {
+                let Rgb([r, g, b]) =
+                    coloring::gradient(self.palette.as_deref(), i as f64 * length / texels as f64);
+                (r, g, b)
+            })
+            .collect();
+
+        (colors, length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fractals::color_rainbow,
+        palette::{Interpolation, Palette},
+    };
+
+    fn frame(palette: Option<Palette>) -> Frame {
+        Frame {
+            width: 1,
+            height: 1,
+            positions: vec![0.0],
+            palette: palette.map(Arc::new),
+        }
+    }
+
+    #[test]
+    fn gradient_is_one_repetition() {
+        let (colors, length) = frame(None).gradient();
+        assert_eq!(length, 360.0);
+        assert_eq!(colors[90], color_rainbow(90, u32::MAX).0.into());
+
+        let palette = Palette::new(
+            vec![(0.0, Rgb([0, 0, 0])), (0.5, Rgb([255, 255, 255]))],
+            Interpolation::Rgb,
+        )
+        .with_length(64.0);
+        let (colors, length) = frame(Some(palette)).gradient();
+        assert_eq!(length, 64.0);
+        assert_eq!(colors.len(), GRADIENT_TEXELS);
+        assert_eq!(colors[GRADIENT_TEXELS / 2], (255, 255, 255));
+        assert_eq!(colors[GRADIENT_TEXELS / 4], (128, 128, 128));
+    }
+
+    #[test]
+    fn colors_are_packed_below_gradient() {
+        assert_eq!(pack(Rgb([0, 0, 0])), -1.0);
+        assert_eq!(pack(Rgb([0, 1, 2])), -259.0);
+        assert_eq!(pack(Rgb([255, 255, 255])), -16777216.0);
+    }
+}
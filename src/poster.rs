@@ -0,0 +1,213 @@
+///! Renders images too big to keep in memory, like posters.
+///! Image is drawn band by band, every band is colored and streamed to the PNG encoder,
+///! so only one band is kept in memory, no matter how tall the image is.
+///! Bands are drawn tile by tile, outcomes of the iteration are big, and kept for one tile only,
+///! the band is kept as colors, 3 bytes per pixel.
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::atomic::AtomicBool,
+    time::Instant,
+};
+
+use rayon::{
+    iter::{IndexedParallelIterator, ParallelIterator},
+    slice::ParallelSliceMut,
+};
+
+use crate::{
+    coloring::{Coloring, Colorizer},
+    fractal_builder::Context,
+    fractals::{Area, Floating, FractalFunction, PixelResult, Statistics},
+    supersampling,
+};
+
+/// Number of rows drawn at once
+const BAND_HEIGHT: u32 = 64;
+
+/// Columns of the band drawn at once
+const TILE_WIDTH: u32 = 256;
+
+/// Longer side of the preview, drawn for colorings which need the whole image
+const PREVIEW_SIZE: u32 = 512;
+
+/// Memory is bounded by TILE_WIDTH x BAND_HEIGHT PixelResults, and width x BAND_HEIGHT colors,
+/// whatever the height of the image
+pub fn render<F: Floating>(
+    context: &Context<F>,
+    fractal: &dyn FractalFunction<F>,
+    path: &Path,
+) -> Result<Statistics, png::EncodingError> {
+    let start = Instant::now();
+
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, context.img_width, context.img_height);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    let mut stream = writer.stream_writer();
+
+    let width = context.img_width as usize;
+    let mut pixels = vec![PixelResult::default(); (TILE_WIDTH * BAND_HEIGHT) as usize];
+    let mut band = vec![0u8; width * BAND_HEIGHT as usize * 3];
+
+    // Histogram of the whole image does not fit in memory, preview stands for it
+    let preview = if context.coloring == Coloring::Histogram {
+        preview(context, fractal)
+    } else {
+        vec![]
+    };
+    let colorizer = Colorizer::new(context, &preview);
+
+    let mut stats = Statistics::default();
+
+    for y in (0..context.img_height).step_by(BAND_HEIGHT as usize) {
+        let height = (context.img_height - y).min(BAND_HEIGHT);
+        let band = &mut band[..width * height as usize * 3];
+
+        for x in (0..context.img_width).step_by(TILE_WIDTH as usize) {
+            let tile = Area {
+                x,
+                y,
+                width: (context.img_width - x).min(TILE_WIDTH),
+                height,
+                step: 1,
+            };
+            let pixels = &mut pixels[..(tile.width * tile.height) as usize];
+
+            stats = stats
+                + pixels
+                    .par_chunks_mut(tile.width as usize)
+                    .enumerate()
+                    .map(|(id, chunk)| {
+                        let area = Area {
+                            y: y + id as u32,
+                            height: 1,
+                            ..tile
+                        };
+
+                        fractal.draw(context, &area, chunk)
+                    })
+                    .reduce(Statistics::default, |a, b| a + b);
+
+            // Adaptive sampling does not see neighbours from other tiles, not a big deal
+            let (colors, sampled) = supersampling::colorize(
+                context,
+                &colorizer,
+                fractal,
+                pixels,
+                &tile,
+                &AtomicBool::new(false),
+            );
+            stats = stats + sampled;
+
+            for (row, tile_colors) in band
+                .chunks_mut(width * 3)
+                .zip(colors.chunks(tile.width as usize))
+            {
+                let row = &mut row[x as usize * 3..(x + tile.width) as usize * 3];
+
+                for (color, rgb) in tile_colors.iter().zip(row.chunks_mut(3)) {
+                    rgb.copy_from_slice(&color.0);
+                }
+            }
+        }
+
+        stream.write_all(band)?;
+
+        println!(
+            "poster {}%",
+            (y + height) as u64 * 100 / context.img_height as u64
+        );
+    }
+
+    stream.finish()?;
+
+    println!("poster took {}, {:?}", start.elapsed().as_millis(), stats);
+
+    Ok(stats)
+}
+
+/// Every step-th pixel of the image in both directions, PREVIEW_SIZE of them along the longer side
+fn preview<F: Floating>(
+    context: &Context<F>,
+    fractal: &dyn FractalFunction<F>,
+) -> Vec<PixelResult> {
+    let step = (context.img_width.max(context.img_height) + PREVIEW_SIZE - 1) / PREVIEW_SIZE;
+    let width = (context.img_width + step - 1) / step;
+    let height = (context.img_height + step - 1) / step;
+
+    let mut pixels = vec![PixelResult::default(); (width * height) as usize];
+    pixels
+        .par_chunks_mut(width as usize)
+        .enumerate()
+        .for_each(|(id, chunk)| {
+            let area = Area {
+                x: 0,
+                y: id as u32 * step,
+                width,
+                height: 1,
+                step,
+            };
+
+            fractal.draw(context, &area, chunk);
+        });
+
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        coloring::{Coloring, Interior},
+        fractals::{color_rainbow, Mandelbrot, PoI},
+        supersampling::Sampling,
+    };
+    use std::marker::PhantomData;
+
+    #[test]
+    fn poster_matches_drawing_at_once() {
+        // Few bands, last one not full, of two tiles, last one not full
+        let context = Context {
+            img_width: TILE_WIDTH + 37,
+            img_height: 3 * BAND_HEIGHT + 7,
+            pinhole_step: 1.0,
+            sampling: Sampling::Single,
+            coloring: Coloring::Rainbow,
+            interior: Interior::Black,
+            palette: None,
+            poi: PoI {
+                origin_x: -0.5,
+                origin_y: 0.0,
+                pinhole_size: 3.0,
+                limit: 300,
+            },
+        };
+        let fractal = Mandelbrot::<f64>(PhantomData);
+
+        let path = std::env::temp_dir().join("fractal_poster_test.png");
+        render(&context, &fractal, &path).unwrap();
+
+        let whole = Area {
+            x: 0,
+            y: 0,
+            width: context.img_width,
+            height: context.img_height,
+            step: 1,
+        };
+        let mut pixels = vec![PixelResult::default(); (whole.width * whole.height) as usize];
+        fractal.draw(&context, &whole, &mut pixels);
+
+        let poster = image::open(&path).unwrap().to_rgb8();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(poster.dimensions(), (whole.width, whole.height));
+        for (x, y, rgb) in poster.enumerate_pixels() {
+            let pixel = pixels[(y * whole.width + x) as usize];
+            assert_eq!(*rgb, color_rainbow(pixel.iteration, context.poi.limit));
+        }
+    }
+}
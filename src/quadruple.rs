@@ -11,6 +11,8 @@
 use std::cmp::Ordering;
 use std::ops::{Add, AddAssign, Mul, MulAssign, Sub, SubAssign};
 
+use crate::fractals::{Abs, Epsilon};
+
 // For float p = 24, double p = 53 ((2 << 27) + 1).
 const SPLIT: f64 = ((2 << 27) + 1) as f64;
 
@@ -35,6 +37,13 @@ impl From<f64> for Quad {
     }
 }
 
+/// Low part is lost, it does not fit anyway
+impl From<Quad> for f64 {
+    fn from(a: Quad) -> Self {
+        a.hi + a.lo
+    }
+}
+
 /// Operator +
 impl Add for Quad {
     type Output = Self;
@@ -122,6 +131,24 @@ impl MulAssign for Quad {
         *self = *self * rhs;
     }
 }
+impl Epsilon for Quad {
+    fn epsilon() -> Self {
+        // Two doubles give 106 bits of significand
+        Quad::from(2.0f64.powi(-105))
+    }
+}
+
+impl Abs for Quad {
+    fn abs(self) -> Self {
+        // Normalized, the sign is the sign of the high part
+        if self.hi < 0.0 {
+            Quad::new(-self.lo, -self.hi)
+        } else {
+            self
+        }
+    }
+}
+
 // TODO: Partial ordering:
 // https://doc.rust-lang.org/std/cmp/trait.PartialOrd.html
 
@@ -2,10 +2,10 @@
 //! https://ciechanow.ski/exposing-floating-point/
 
 use std::cmp::Ordering;
-use std::ops::{Add, Mul, Sub};
-use std::{fmt, mem};
+use std::fmt;
+use std::ops::{Add, AddAssign, Mul, MulAssign, Sub, SubAssign};
 
-use rug::ops::Pow;
+use crate::fractals::{Abs, Epsilon};
 
 // Lets try binary32 first.
 // exp range [-126; 127]
@@ -16,8 +16,19 @@ const EXPONENT_MASK: u32 = 0xFF;
 const SIGNIFICAND_WIDTH: u32 = 23;
 const SIGNIFICAND_MASK: u32 = 0x7FFFFF;
 
+// GRS - Guard, Round, Sticky bits. They are kept just below the least significant
+// bit of the significand during the operation, and decide about rounding at the end.
+const GRS_WIDTH: u32 = 3;
+const GRS_MASK: u64 = 0b111;
+
+// binary64 layout, needed for conversion from f64
+const F64_BIAS: i32 = 1023;
+const F64_EXPONENT_MASK: u64 = 0x7FF;
+const F64_SIGNIFICAND_WIDTH: u32 = 52;
+const F64_SIGNIFICAND_MASK: u64 = 0xFFFFFFFFFFFFF;
+
 // clone + copy to be able to do: x + x etc.
-#[derive(Debug, Default, PartialEq, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy)]
 pub struct SoftFloat {
     negative: bool,
     exponent: u32,
@@ -41,6 +52,10 @@ impl SoftFloat {
         self.negative && self.exponent == EXPONENT_MASK && self.significand == 0
     }
 
+    pub fn is_infinity(&self) -> bool {
+        self.exponent == EXPONENT_MASK && self.significand == 0
+    }
+
     pub fn is_neg_zero(&self) -> bool {
         self.negative && self.exponent == 0 && self.significand == 0
     }
@@ -84,6 +99,23 @@ impl SoftFloat {
         }
     }
 
+    pub fn inf(negative: bool) -> Self {
+        if negative {
+            Self::neg_inf()
+        } else {
+            Self::pos_inf()
+        }
+    }
+
+    /// Quiet NaN, the most significant bit of the significand is set
+    pub fn nan() -> Self {
+        Self {
+            negative: false,
+            exponent: EXPONENT_MASK,
+            significand: 1 << (SIGNIFICAND_WIDTH - 1),
+        }
+    }
+
     pub fn zero(negative: bool) -> Self {
         Self {
             negative: negative,
@@ -92,89 +124,103 @@ impl SoftFloat {
         }
     }
 
-    pub fn normalize_mul(&mut self) {
-        println!("normalizing {:?}", self);
+    /// Bit representation of the number, as in f32::to_bits
+    pub fn to_bits(&self) -> u32 {
+        (self.negative as u32) << (EXPONENT_WIDTH + SIGNIFICAND_WIDTH)
+            | (self.exponent << SIGNIFICAND_WIDTH)
+            | (self.significand & SIGNIFICAND_MASK as u64) as u32
+    }
 
-        if self.is_nan() || self.is_neg_infinity() || self.is_pos_infinity() {
-            // Nothing to normalize
-            return;
+    /// Returns biased exponent and significand with the implicit bit made explicit.
+    /// Value of the number is significand * 2^(exponent - BIAS - SIGNIFICAND_WIDTH).
+    /// Subnormals have the same scale as the smallest normal number, hence exponent 1.
+    fn unpack(&self) -> (i32, u64) {
+        if self.exponent == 0 {
+            (1, self.significand)
+        } else {
+            (
+                self.exponent as i32,
+                self.significand | (1 << SIGNIFICAND_WIDTH),
+            )
         }
+    }
 
-        if self.is_subnormal() {
-            todo!()
+    /// Shift right, but do not lose information that something was shifted out:
+    /// all bits falling off are OR-ed into the least significant bit (sticky bit).
+    fn shift_right_sticky(significand: u64, shift: u32) -> u64 {
+        if shift == 0 {
+            significand
+        } else if shift >= u64::BITS {
+            (significand != 0) as u64
+        } else {
+            let lost = significand & ((1 << shift) - 1);
+            (significand >> shift) | (lost != 0) as u64
+        }
+    }
+
+    /// Builds the number out of the result of an operation, the single place
+    /// where normalization and rounding happens.
+    /// Value of the number is significand * 2^(exponent - BIAS - SIGNIFICAND_WIDTH - GRS_WIDTH),
+    /// so the GRS bits are expected at the bottom of the significand. Leading one
+    /// can be at any position, exponent can be out of range as well.
+    fn round_pack(negative: bool, mut exponent: i32, mut significand: u64) -> Self {
+        if significand == 0 {
+            return Self::zero(negative);
         }
 
-        // GRS - Guard, Round, Sticky bits. They are the very first bits, just
-        // after overflow
+        // Normalize, so the implicit bit lands just above the significand and GRS bits
+        const LEADING_BIT: u32 = SIGNIFICAND_WIDTH + GRS_WIDTH;
+        let leading_bit = u64::BITS - 1 - significand.leading_zeros();
 
-        // TODO: Sticky bit is actual: Logical OR of ALL other bits after Guard & R bits
-        const GRS_WIDTH: u32 = 3;
-        const GRS_MASK: u64 = 0b111;
-        let mut grs = (self.significand >> (SIGNIFICAND_WIDTH - GRS_WIDTH)) & GRS_MASK;
+        if leading_bit > LEADING_BIT {
+            let shift = leading_bit - LEADING_BIT;
+            significand = Self::shift_right_sticky(significand, shift);
+            exponent += shift as i32;
+        } else {
+            let shift = LEADING_BIT - leading_bit;
+            significand <<= shift;
+            exponent -= shift as i32;
+        }
 
-        // As for now (32bit float implementation) u64 buffer is more than
-        // required to keep the resulting value.
-        // 0 in significand with implicit 1 is 8388608 in binary, 1 << 23,
-        // so two smallest values will ever be multiplied is 1 << 23 * 1 << 23,
-        // that results with 1 << 46. Shift back by width of significand ( >> 23)
-        // To have bits in correct place, from that place normalization can be done.
-        self.significand >>= SIGNIFICAND_WIDTH;
-
-        // If there is anything outside 23 bits range - shift it
-        // 1 is the implicit bit on 24th position
-        // TODO: hope compiler hoists those values
-        while (self.significand & !(SIGNIFICAND_MASK as u64)) > (1 << SIGNIFICAND_WIDTH) {
-            // Does this overflow?
-            // Move grs by one, get guard bit from significand
-            grs >>= 1;
-            grs |= (self.significand & 1) << 2;
-
-            println!("Shifting significand grs {:b}", grs);
-            self.significand >>= 1;
-            self.exponent += 1;
-        }
-
-        // Make bit on 24th position implicit
-        self.significand &= SIGNIFICAND_MASK as u64;
-
-        if grs & 0b100 != 0 {
-            if grs & 0b11 == 0 {
-                // 100, exactly half way, round to even
-                if self.significand & 0b1 == 1 {
-                    // mantissa is odd, add 1
-                    self.significand += 1;
-                } // else mantissa is even, noop
-            } else {
-                // 101, 110, 111, more than half way, round up - add 1
-
-                // TODO: in case of overflow reset significand, and increase exponent?
-                // GRS - Action
-                // 0xx - round down = do nothing (x means any bit value, 0 or 1)
-                // 100 - this is a tie: round up if the mantissa's bit just before G is 1, else round down=do nothing
-                // 101 - round up
-                // 110 - round up
-                // 111 - round up
-
-                // Rounding up is done by adding 1 to the mantissa in the mantissa's least significant bit position just before G.
-                // If the mantissa overflows (its 23 least significant bits that you will store become zeroes), you have to add 1
-                // to the exponent. If the exponent overflows, you set the number to +infinity or -infinity depending on the number's sign.
-                // In the case of a tie, you add 1 to the mantissa if the mantissa is odd and you add nothing if it's even.
-                // That's what makes the result rounded to the nearest even value.
-                self.significand += 1
-            }
-        } // 0xx Round down - discard GRS bits
-    }
+        if exponent <= 0 {
+            // Too small to be normal, denormalize to the scale of exponent 1,
+            // and mark it as a subnormal.
+            significand = Self::shift_right_sticky(significand, (1 - exponent) as u32);
+            exponent = 0;
+        }
+
+        let grs = significand & GRS_MASK;
+        significand >>= GRS_WIDTH;
+
+        // GRS - Action
+        // 0xx - round down = do nothing (x means any bit value, 0 or 1)
+        // 100 - this is a tie: round up if the mantissa's bit just before G is 1, else round down=do nothing
+        // 101 - round up
+        // 110 - round up
+        // 111 - round up
+        if grs > 0b100 || (grs == 0b100 && significand & 1 == 1) {
+            significand += 1;
+        }
+
+        if significand >> (SIGNIFICAND_WIDTH + 1) != 0 {
+            // Rounding overflowed the significand: 1.11...1 + 0.00...1 = 10.00...0
+            significand >>= 1;
+            exponent += 1;
+        } else if exponent == 0 && significand >> SIGNIFICAND_WIDTH != 0 {
+            // Subnormal got rounded up to the smallest normal number
+            exponent = 1;
+        }
 
-    // 1.1100,0000,0000,0000,0000,00112 * 22
-    // TODO: Handle NaNs
-    // The easiest way to obtain NaN directly is by using NAN macro.
-    // In practice though, NaN arises in the following set of operations:
+        if exponent >= EXPONENT_MASK as i32 {
+            return Self::inf(negative);
+        }
 
-    // ±0.0 multiplied by ±infinity
-    // −infinity added to +infinity
-    // ±0.0 divided by ±0.0
-    // ±infinity divided by ±infinity
-    // square root of a negative number (−0.0 is fine though!)
+        Self {
+            negative,
+            exponent: exponent as u32,
+            significand: significand & SIGNIFICAND_MASK as u64,
+        }
+    }
 
     // By default the result of any operation involving NaNs will result in a NaN as well.
     // That’s one of the reasons why compiler can’t optimize seemingly simple cases like
@@ -189,14 +235,7 @@ impl fmt::Display for SoftFloat {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         // For now, go the easiest way, while expanding the resolution,
         // this might be pretty tricky to implement.
-
-        let mut bits: u32 = 0;
-
-        bits = (self.negative as u32) << (EXPONENT_WIDTH + SIGNIFICAND_WIDTH)
-            | (self.exponent << SIGNIFICAND_WIDTH)
-            | (self.significand & SIGNIFICAND_MASK as u64) as u32;
-
-        write!(f, "{}", f32::from_bits(bits))
+        write!(f, "{}", f32::from(*self))
     }
 }
 
@@ -216,55 +255,122 @@ impl From<f32> for SoftFloat {
     }
 }
 
+impl From<SoftFloat> for f32 {
+    fn from(a: SoftFloat) -> Self {
+        f32::from_bits(a.to_bits())
+    }
+}
+
+/// Exact, every f32 fits in f64
+impl From<SoftFloat> for f64 {
+    fn from(a: SoftFloat) -> Self {
+        f32::from(a) as f64
+    }
+}
+
 impl From<f64> for SoftFloat {
     fn from(a: f64) -> Self {
-        // SoftFloat::from(a as f32)
-        todo!()
+        let bits = a.to_bits();
+
+        let negative = (bits >> 63) == 1;
+        let exponent = (bits >> F64_SIGNIFICAND_WIDTH) & F64_EXPONENT_MASK;
+        let significand = bits & F64_SIGNIFICAND_MASK;
+
+        if exponent == F64_EXPONENT_MASK {
+            if significand != 0 {
+                return SoftFloat::nan();
+            }
+            return SoftFloat::inf(negative);
+        }
+
+        // Same story as in unpack(), subnormals have the scale of exponent 1
+        let (exponent, significand) = if exponent == 0 {
+            (1, significand)
+        } else {
+            (exponent as i32, significand | (1 << F64_SIGNIFICAND_WIDTH))
+        };
+
+        // Rebias, and move binary point from 52nd position to the one round_pack expects.
+        // Conversion is rounded once, so it gives the same result as 'a as f32'.
+        let exponent = exponent - F64_BIAS + BIAS as i32 + (SIGNIFICAND_WIDTH + GRS_WIDTH) as i32
+            - F64_SIGNIFICAND_WIDTH as i32;
+
+        SoftFloat::round_pack(negative, exponent, significand)
     }
 }
 
 impl Add for SoftFloat {
     type Output = Self;
 
-    fn add(mut self, mut rhs: Self) -> Self {
-        // TODO: ==0?
-        if self.exponent > 0 && rhs.exponent > 0 {
-            // 10^5 + 10^3
+    fn add(self, rhs: Self) -> Self {
+        if self.is_nan() || rhs.is_nan() {
+            return SoftFloat::nan();
         }
 
-        if self.exponent > rhs.exponent {
-            // 2 > -4
-            // 5 > 3
-            // TODO: operate on biased exponents?
-            // let gap = (self.exponent - rhs.exponent).abs();
+        if self.is_infinity() || rhs.is_infinity() {
+            // −infinity added to +infinity
+            if self.is_infinity() && rhs.is_infinity() && self.negative != rhs.negative {
+                return SoftFloat::nan();
+            }
+
+            return if self.is_infinity() { self } else { rhs };
         }
-        todo!()
-    }
-}
 
-//100000 + 1000
-// 0.0001 -> 1 * 10^-4
-// 500.0  -> 5 * 10^2
-// gap = 6
+        // Make sure 'a' has greater magnitude, exponent goes first, so comparing
+        // exponent and significand as one number does the job
+        let (a, b) = if (self.exponent, self.significand) >= (rhs.exponent, rhs.significand) {
+            (self, rhs)
+        } else {
+            (rhs, self)
+        };
 
-// 500 0000 -> 5*10^6
+        let (a_exp, a_sig) = a.unpack();
+        let (b_exp, b_sig) = b.unpack();
 
-// 500 0000 +
-// 000 0001
+        // Make room for GRS bits, and align smaller number to the exponent of
+        // the bigger one. Bits shifted out are not lost, they stick to the S bit.
+        let a_sig = a_sig << GRS_WIDTH;
+        let b_sig = SoftFloat::shift_right_sticky(b_sig << GRS_WIDTH, (a_exp - b_exp) as u32);
 
-// 500. 0001 normalize
-// 5 000001 2
+        if a.negative == b.negative {
+            // 500 0000 +
+            // 000 0001
+            // 500. 0001 normalize
+            SoftFloat::round_pack(a.negative, a_exp, a_sig + b_sig)
+        } else {
+            let significand = a_sig - b_sig;
 
-// 0.99 -> 9.9 * 10^-1
-// 0.01 -> 1   * 10^-2
+            if significand == 0 {
+                // x - x is +0 when rounding to nearest, even for -0 - (-0)
+                return SoftFloat::zero(false);
+            }
+
+            // 99 (^2) - 98 = 1 -> leading bit went way down, round_pack shifts it back
+            SoftFloat::round_pack(a.negative, a_exp, significand)
+        }
+    }
+}
+
+impl AddAssign for SoftFloat {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
 
-// 99 (^2) + 1 = 100
-// 1 * 10^0
 impl Sub for SoftFloat {
     type Output = Self;
 
-    fn sub(mut self, mut rhs: Self) -> Self::Output {
-        todo!();
+    fn sub(self, mut rhs: Self) -> Self::Output {
+        // a - b = a + (-b)
+        rhs.negative = !rhs.negative;
+
+        self + rhs
+    }
+}
+
+impl SubAssign for SoftFloat {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
     }
 }
 
@@ -273,57 +379,84 @@ impl Mul for SoftFloat {
 
     fn mul(self, rhs: Self) -> Self {
         let negative = self.negative ^ rhs.negative;
-        // TODO: checked overflow may become handy
-        // a_exp + b_exp = a_exp - bias + b_exp - bias = a_exp + b_exp - 2bias
-        // => a_exp + b_exp -2bias + bias == added real exponents and biased
 
-        if self.is_zero() || rhs.is_zero() {
-            return Self::zero(negative);
+        if self.is_nan() || rhs.is_nan() {
+            return SoftFloat::nan();
         }
 
-        let exponent = self.exponent + rhs.exponent - BIAS;
-
-        if exponent > EXPONENT_MASK {
-            if negative {
-                return SoftFloat::neg_inf();
-            } else {
-                return SoftFloat::pos_inf();
+        if self.is_infinity() || rhs.is_infinity() {
+            // ±0.0 multiplied by ±infinity
+            if self.is_zero() || rhs.is_zero() {
+                return SoftFloat::nan();
             }
-        }
 
-        // TODO: do biasing?
-        // 4 + 5 = 4 - 127 + 5 - 127 = 9 - 254 = -245
-        //
-        let mut a_sig = self.significand;
-        if !self.is_subnormal() {
-            a_sig |= 1 << SIGNIFICAND_WIDTH;
+            return SoftFloat::inf(negative);
         }
 
-        // TODO: make sure mul of mixed subnormal and normal works
-        let mut b_sig = rhs.significand;
-        if !rhs.is_subnormal() {
-            b_sig |= 1 << SIGNIFICAND_WIDTH;
+        if self.is_zero() || rhs.is_zero() {
+            return Self::zero(negative);
         }
 
+        let (a_exp, a_sig) = self.unpack();
+        let (b_exp, b_sig) = rhs.unpack();
+
+        // As for now (32bit float implementation) u64 buffer is more than
+        // required to keep the resulting value.
+        // 0 in significand with implicit 1 is 8388608 in binary, 1 << 23,
+        // so two smallest values will ever be multiplied is 1 << 23 * 1 << 23,
+        // that results with 1 << 46.
         let significand = a_sig * b_sig;
 
-        // TODO: normalize result
-        let mut res = SoftFloat {
-            negative,
-            exponent,
-            significand,
-        };
+        // a_exp + b_exp = a_exp - bias + b_exp - bias = a_exp + b_exp - 2bias
+        // => a_exp + b_exp -2bias + bias == added real exponents and biased.
+        // Product has binary point at 46th position, round_pack expects it at 26th.
+        let exponent = a_exp + b_exp - BIAS as i32 - SIGNIFICAND_WIDTH as i32 + GRS_WIDTH as i32;
+
+        SoftFloat::round_pack(negative, exponent, significand)
+    }
+}
+
+impl MulAssign for SoftFloat {
+    fn mul_assign(&mut self, rhs: Self) {
+        *self = *self * rhs;
+    }
+}
 
-        res.normalize_mul();
+impl Epsilon for SoftFloat {
+    fn epsilon() -> Self {
+        SoftFloat::from(f32::EPSILON)
+    }
+}
 
-        res
+impl Abs for SoftFloat {
+    fn abs(self) -> Self {
+        SoftFloat {
+            negative: false,
+            ..self
+        }
+    }
+}
+
+/// Agrees with the comparison, +0 == -0 and NaN != NaN, as in f64
+impl PartialEq for SoftFloat {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
     }
 }
 
 impl PartialOrd for SoftFloat {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        // TODO: check for submormals, inf's, nan's
+        if self.is_nan() || other.is_nan() {
+            return None;
+        }
+
+        // +0 == -0
+        if self.is_zero() && other.is_zero() {
+            return Some(Ordering::Equal);
+        }
+
         // Numbers are in normalized from, should be easy, eh?!
+        // Subnormals have exponent 0, infinities the max one, so they fall into place.
         if self.negative == other.negative {
             // Both positive, or negative
 
@@ -753,7 +886,15 @@ mod tests {
             (-0.00001, -10000.0, -10000.00001),
         ] {
             // println!("{} + {} = {}", a, b, c);
-            assert_eq!(SoftFloat::from(a) + SoftFloat::from(b), SoftFloat::from(c));
+            let sum = SoftFloat::from(a) + SoftFloat::from(b);
+
+            // Decimal result is not always representable, like 3.14 + 2.0 gives 5.1400003,
+            // so expect to be bit exact with the hardware, and close to the decimal one.
+            assert_eq!(sum, SoftFloat::from(a as f32 + b as f32));
+            assert!(
+                (f32::from(sum) - c as f32).abs()
+                    <= f32::EPSILON * (f64::abs(a) + f64::abs(b)) as f32
+            );
         }
     }
 
@@ -846,7 +987,211 @@ mod tests {
             // Both negative
         ] {
             //println!(" TESTING {} - {} = {}", a, b, c);
-            assert_eq!(SoftFloat::from(a) - SoftFloat::from(b), SoftFloat::from(c));
+            let diff = SoftFloat::from(a) - SoftFloat::from(b);
+
+            assert_eq!(diff, SoftFloat::from(a as f32 - b as f32));
+            assert!(
+                (f32::from(diff) - c as f32).abs()
+                    <= f32::EPSILON * (f64::abs(a) + f64::abs(b)) as f32
+            );
+        }
+    }
+
+    #[test]
+    fn convert_from_double_works() {
+        for a in [
+            0.0,
+            -0.0,
+            1.0,
+            0.1,
+            -123456978.000069696969,
+            3.4028235e38,
+            // Rounds up to infinity
+            3.4028236e38,
+            1e39,
+            -1e39,
+            // Subnormals, and values too small for binary32
+            1.17549435e-38,
+            1e-40,
+            -1.4e-45,
+            1e-46,
+            f64::MIN_POSITIVE,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+        ] {
+            assert_eq!(SoftFloat::from(a), SoftFloat::from(a as f32));
+        }
+
+        assert!(SoftFloat::from(f64::NAN).is_nan());
+    }
+
+    #[test]
+    fn special_values_work() {
+        let inf = SoftFloat::pos_inf();
+        let zero = SoftFloat::zero(false);
+        let one = SoftFloat::from(1.0);
+
+        assert!((inf + SoftFloat::neg_inf()).is_nan());
+        assert!((inf - inf).is_nan());
+        assert!((inf * zero).is_nan());
+        assert!((SoftFloat::nan() + one).is_nan());
+        assert!(SoftFloat::nan().partial_cmp(&one).is_none());
+
+        assert_eq!(inf + one, inf);
+        assert_eq!(one - inf, SoftFloat::neg_inf());
+        assert_eq!(inf * SoftFloat::from(-2.0), SoftFloat::neg_inf());
+        assert_eq!(SoftFloat::neg_inf().abs(), inf);
+        assert_eq!(SoftFloat::from(-2.5).abs(), SoftFloat::from(2.5));
+
+        // Overflow
+        assert_eq!(SoftFloat::from(f32::MAX) * SoftFloat::from(2.0), inf);
+        assert_eq!(SoftFloat::from(f32::MAX) + SoftFloat::from(f32::MAX), inf);
+
+        assert!(SoftFloat::zero(true) == SoftFloat::zero(true));
+        assert!(SoftFloat::zero(true) >= SoftFloat::zero(false));
+        assert_eq!(SoftFloat::zero(true), SoftFloat::zero(false));
+        assert_ne!(SoftFloat::nan(), SoftFloat::nan());
+        assert_ne!(SoftFloat::nan(), one);
+    }
+
+    #[test]
+    fn subnormals_work() {
+        let min = f32::MIN_POSITIVE;
+        let sub = f32::from_bits(1);
+
+        for (a, b) in [
+            (min, 0.5),
+            (min, 0.75),
+            (sub, 0.5),
+            (sub, 1.5),
+            (sub * 3.0, 0.5),
+            (min * 0.25, 4.0),
+            (1e-20, 1e-20),
+            (1e-30, -1e-10),
+        ] {
+            assert_eq!(
+                SoftFloat::from(a) * SoftFloat::from(b),
+                SoftFloat::from(a * b)
+            );
+        }
+
+        for (a, b) in [(min, -sub), (min * 0.5, min * 0.5), (sub, sub), (-sub, sub)] {
+            assert_eq!(
+                SoftFloat::from(a) + SoftFloat::from(b),
+                SoftFloat::from(a + b)
+            );
+            assert_eq!(
+                SoftFloat::from(a) - SoftFloat::from(b),
+                SoftFloat::from(a - b)
+            );
+        }
+    }
+
+    #[test]
+    fn random_operations_match_hardware() {
+        // Poor man's fuzzing, xorshift to not depend on rand crate
+        let mut state = 0x2545F4914F6CDD1Du64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+
+            // Keep exponents close to each other every other time,
+            // so cancellation is tested as well
+            let bits = state as u32;
+            if bits & 1 == 0 {
+                f32::from_bits(bits & 0x8FFF_FFFF | 0x3000_0000)
+            } else {
+                f32::from_bits(bits)
+            }
+        };
+
+        for _ in 0..100_000 {
+            let (a, b) = (next(), next());
+
+            if a.is_nan() || b.is_nan() {
+                continue;
+            }
+
+            let (sa, sb) = (SoftFloat::from(a), SoftFloat::from(b));
+
+            for (soft, hard) in [(sa + sb, a + b), (sa - sb, a - b), (sa * sb, a * b)] {
+                if hard.is_nan() {
+                    assert!(soft.is_nan(), "{} {}", a, b);
+                } else {
+                    assert_eq!(soft, SoftFloat::from(hard), "{} {}", a, b);
+                }
+            }
+
+            assert_eq!(sa.partial_cmp(&sb), a.partial_cmp(&b));
+        }
+    }
+
+    #[test]
+    fn mandelbrot_matches_f32() {
+        use crate::coloring::{Coloring, Interior};
+        use crate::fractal_builder::Context;
+        use crate::fractals::{Area, FractalFunction, Mandelbrot, PixelResult, PoI};
+        use crate::supersampling::Sampling;
+        use std::marker::PhantomData;
+
+        const SIZE: u32 = 64;
+
+        let context = Context {
+            img_width: SIZE,
+            img_height: SIZE,
+            pinhole_step: SoftFloat::from(1.0),
+            sampling: Sampling::Single,
+            coloring: Coloring::Rainbow,
+            interior: Interior::Black,
+            palette: None,
+            poi: PoI {
+                origin_x: SoftFloat::from(-0.743643887037158704752191506114774),
+                origin_y: SoftFloat::from(0.131825904205311970493132056385139),
+                pinhole_size: SoftFloat::from(0.01),
+                limit: 500,
+            },
+        };
+
+        let mut pixels = vec![PixelResult::default(); (SIZE * SIZE) as usize];
+        let area = Area {
+            x: 0,
+            y: 0,
+            width: SIZE,
+            height: SIZE,
+            step: 1,
+        };
+        Mandelbrot::<SoftFloat>(PhantomData).draw(&context, &area, &mut pixels);
+
+        // f32 does not implement From<f64>, so it cannot be Floating.
+        // Below is the Mandelbrot<F> kernel with F = f32 spelled out.
+        let pinhole_size = 0.01f64 as f32;
+        let center_x = -0.743643887037158704752191506114774f64 as f32 - pinhole_size * 0.5;
+        let center_y = 0.131825904205311970493132056385139f64 as f32 - pinhole_size * 0.5;
+
+        for pixel_y in 0..SIZE {
+            let y0 = (pixel_y as f64 / SIZE as f64) as f32 * pinhole_size + center_y;
+
+            for pixel_x in 0..SIZE {
+                let x0 = (pixel_x as f64 / SIZE as f64) as f32 * pinhole_size + center_x;
+
+                let (mut x, mut y, mut x2, mut y2, mut sum) = (0.0f32, 0.0, 0.0, 0.0, 0.0);
+                let mut iteration = 0;
+
+                while sum < 4.0 && iteration < context.poi.limit {
+                    y = (x + x) * y + y0;
+                    x = x2 - y2 + x0;
+                    x2 = x * x;
+                    y2 = y * y;
+                    sum = x2 + y2;
+                    iteration += 1;
+                }
+
+                assert_eq!(
+                    pixels[(pixel_y * SIZE + pixel_x) as usize].iteration,
+                    iteration
+                );
+            }
         }
     }
 }
@@ -0,0 +1,373 @@
+///! Anti-aliasing, pixel color is the average of many samples taken within it.
+///! Samples are drawn by any FractalFunction, on the image few times bigger than the real one,
+///! so sub-pixel positions become whole pixels of it.
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use image::Rgb;
+use rayon::{
+    iter::{IndexedParallelIterator, ParallelIterator},
+    slice::ParallelSliceMut,
+};
+
+use crate::{
+    coloring::Colorizer,
+    fractal_builder::Context,
+    fractals::{Area, Floating, FractalFunction, PixelResult, PoI, Statistics},
+};
+
+/// Samples taken per pixel
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(dead_code)]
+pub enum Sampling {
+    /// One sample, in the corner of the pixel
+    Single,
+    /// n x n samples, in the centers of the regular grid cells
+    Grid(u32),
+    /// n x n samples, each at random position within its grid cell
+    Jittered(u32),
+    /// Grid(n), but only for pixels which iterations vary much around them
+    Adaptive(u32),
+}
+
+/// Jittered sample can land on that many positions, along each axis of its cell
+const JITTER_POSITIONS: u32 = 16;
+
+/// Pixel is refined, if variance of iterations in the 3 x 3 neighbourhood is above that
+const ADAPTIVE_VARIANCE: f64 = 16.0;
+
+/// Colors pixels, according to the context.sampling.
+/// pixels are rows of the area, step 1, already drawn with one sample per pixel.
+/// Gives up when cancel is set, the outcome is garbage then.
+pub fn colorize<F: Floating>(
+    context: &Context<F>,
+    colorizer: &Colorizer<F>,
+    fractal: &dyn FractalFunction<F>,
+    pixels: &[PixelResult],
+    area: &Area,
+    cancel: &AtomicBool,
+) -> (Vec<Rgb<u8>>, Statistics) {
+    let width = area.width as usize;
+
+    let single: Vec<_> = pixels.iter().map(|pixel| colorizer.color(pixel)).collect();
+
+    let (samples, factor) = match context.sampling {
+        Sampling::Single => return (single, Statistics::default()),
+        // Cell centers are odd pixels of the image 2n times bigger
+        Sampling::Grid(n) | Sampling::Adaptive(n) => (n, 2 * n),
+        Sampling::Jittered(n) => (n, n * JITTER_POSITIONS),
+    };
+
+    let scaled = scaled(context, factor);
+    let mut colors = single;
+
+    let stats = colors
+        .par_chunks_mut(width)
+        .enumerate()
+        .map(|(row, colors)| {
+            let mut stats = Statistics::default();
+
+            if cancel.load(Ordering::Relaxed) {
+                return stats;
+            }
+
+            let pixel_y = area.y + row as u32;
+
+            for (i, color) in colors.iter_mut().enumerate() {
+                let pixel_x = area.x + i as u32;
+
+                *color = match context.sampling {
+                    Sampling::Adaptive(_) => {
+                        if variance(pixels, width, row, i) <= ADAPTIVE_VARIANCE {
+                            continue;
+                        }
+
+                        stats.refined += 1;
+                        grid(colorizer, &scaled, fractal, pixel_x, pixel_y, samples)
+                    }
+                    Sampling::Jittered(_) => {
+                        jittered(colorizer, &scaled, fractal, pixel_x, pixel_y, samples)
+                    }
+                    _ => grid(colorizer, &scaled, fractal, pixel_x, pixel_y, samples),
+                };
+            }
+
+            stats
+        })
+        .reduce(Statistics::default, |a, b| a + b);
+
+    (colors, stats)
+}
+
+/// Context of the image factor times bigger, showing exactly the same
+fn scaled<F: Floating>(context: &Context<F>, factor: u32) -> Context<F> {
+    Context {
+        img_width: context.img_width * factor,
+        img_height: context.img_height * factor,
+        pinhole_step: context.pinhole_step,
+        sampling: Sampling::Single,
+        coloring: context.coloring,
+        interior: context.interior,
+        palette: context.palette.clone(),
+        poi: PoI {
+            origin_x: context.poi.origin_x,
+            origin_y: context.poi.origin_y,
+            pinhole_size: context.poi.pinhole_size,
+            limit: context.poi.limit,
+        },
+    }
+}
+
+/// Variance of iterations of the pixel and its neighbours, the ones within the pixels
+fn variance(pixels: &[PixelResult], width: usize, row: usize, x: usize) -> f64 {
+    let rows = pixels.len() / width;
+
+    let mut count = 0.0;
+    let mut sum = 0.0;
+    let mut sum_squares = 0.0;
+
+    for j in row.saturating_sub(1)..(row + 2).min(rows) {
+        for i in x.saturating_sub(1)..(x + 2).min(width) {
+            let iteration = pixels[j * width + i].iteration as f64;
+
+            count += 1.0;
+            sum += iteration;
+            sum_squares += iteration * iteration;
+        }
+    }
+
+    let mean = sum / count;
+    sum_squares / count - mean * mean
+}
+
+/// Average of n x n samples, scaled context is 2n times bigger than the image.
+/// Samples are colored as pixels of the image, not of the scaled one.
+fn grid<F: Floating>(
+    colorizer: &Colorizer<F>,
+    scaled: &Context<F>,
+    fractal: &dyn FractalFunction<F>,
+    pixel_x: u32,
+    pixel_y: u32,
+    n: u32,
+) -> Rgb<u8> {
+    let mut samples = vec![PixelResult::default(); n as usize];
+    let mut sum = [0.0; 3];
+
+    for j in 0..n {
+        let area = Area {
+            x: pixel_x * 2 * n + 1,
+            y: pixel_y * 2 * n + 2 * j + 1,
+            width: n,
+            height: 1,
+            step: 2,
+        };
+        fractal.draw(scaled, &area, &mut samples);
+
+        for sample in &samples {
+            accumulate(&mut sum, colorizer.color(sample));
+        }
+    }
+
+    average(sum, n * n)
+}
+
+/// Average of n x n samples, scaled context is n * JITTER_POSITIONS times bigger than the image
+fn jittered<F: Floating>(
+    colorizer: &Colorizer<F>,
+    scaled: &Context<F>,
+    fractal: &dyn FractalFunction<F>,
+    pixel_x: u32,
+    pixel_y: u32,
+    n: u32,
+) -> Rgb<u8> {
+    let mut sample = PixelResult::default();
+    let mut sum = [0.0; 3];
+
+    for j in 0..n {
+        for i in 0..n {
+            let x = (pixel_x * n + i) * JITTER_POSITIONS;
+            let y = (pixel_y * n + j) * JITTER_POSITIONS;
+
+            // Same pixel is jittered the same way every frame, image does not flicker
+            let random = hash(x, y);
+
+            let area = Area {
+                x: x + random % JITTER_POSITIONS,
+                y: y + random / JITTER_POSITIONS % JITTER_POSITIONS,
+                width: 1,
+                height: 1,
+                step: 1,
+            };
+            fractal.draw(scaled, &area, std::slice::from_mut(&mut sample));
+
+            accumulate(&mut sum, colorizer.color(&sample));
+        }
+    }
+
+    average(sum, n * n)
+}
+
+/// Cheap pseudo random number out of the position
+fn hash(x: u32, y: u32) -> u32 {
+    let mut h = x.wrapping_mul(0x9e3779b1) ^ y.wrapping_mul(0x85ebca6b);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb352d);
+    h ^= h >> 15;
+
+    h
+}
+
+/// Colors are averaged in the linear light, not in sRGB
+fn accumulate(sum: &mut [f64; 3], color: Rgb<u8>) {
+    for (sum, channel) in sum.iter_mut().zip(color.0.iter()) {
+        *sum += to_linear(*channel);
+    }
+}
+
+fn average(sum: [f64; 3], count: u32) -> Rgb<u8> {
+    Rgb([
+        to_srgb(sum[0] / count as f64),
+        to_srgb(sum[1] / count as f64),
+        to_srgb(sum[2] / count as f64),
+    ])
+}
+
+/// https://en.wikipedia.org/wiki/SRGB#Transformation
+pub fn to_linear(channel: u8) -> f64 {
+    let c = channel as f64 / 255.0;
+
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn to_srgb(linear: f64) -> u8 {
+    let c = if linear <= 0.0031308 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    };
+
+    (c * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coloring::{Coloring, Interior};
+
+    /// Left half plane is inside of the set, right one escapes at once
+    struct HalfPlane;
+
+    impl FractalFunction<f64> for HalfPlane {
+        fn draw(
+            &self,
+            context: &Context<f64>,
+            area: &Area,
+            pixels: &mut [PixelResult],
+        ) -> Statistics {
+            let center_x = context.poi.origin_x - context.poi.pinhole_size * 0.5;
+
+            for y in 0..area.height {
+                for x in 0..area.width {
+                    let x_offset = (area.x + x * area.step) as f64;
+                    let x0 =
+                        x_offset / context.img_width as f64 * context.poi.pinhole_size + center_x;
+
+                    pixels[(y * area.width + x) as usize].iteration =
+                        if x0 < 0.0 { context.poi.limit } else { 0 };
+                }
+            }
+
+            Statistics::default()
+        }
+    }
+
+    /// Image where the edge between black and red goes through the middle of the pixel 2
+    fn colorize(sampling: Sampling) -> (Vec<Rgb<u8>>, Statistics) {
+        let context = Context {
+            img_width: 5,
+            img_height: 2,
+            pinhole_step: 1.0,
+            sampling,
+            coloring: Coloring::Rainbow,
+            interior: Interior::Black,
+            palette: None,
+            poi: PoI {
+                origin_x: 0.0,
+                origin_y: 0.0,
+                pinhole_size: 5.0,
+                limit: 100,
+            },
+        };
+
+        let whole = Area {
+            x: 0,
+            y: 0,
+            width: 5,
+            height: 2,
+            step: 1,
+        };
+        let mut pixels = vec![PixelResult::default(); 10];
+        HalfPlane.draw(&context, &whole, &mut pixels);
+
+        let colorizer = Colorizer::new(&context, &pixels);
+        super::colorize(
+            &context,
+            &colorizer,
+            &HalfPlane,
+            &pixels,
+            &whole,
+            &AtomicBool::new(false),
+        )
+    }
+
+    const BLACK: Rgb<u8> = Rgb([0, 0, 0]);
+    const RED: Rgb<u8> = Rgb([255, 0, 0]);
+    /// Half black, half red, in the linear light
+    const HALF: Rgb<u8> = Rgb([188, 0, 0]);
+
+    #[test]
+    fn linear_light_conversion_works() {
+        for channel in 0..=255 {
+            assert_eq!(to_srgb(to_linear(channel)), channel);
+        }
+
+        assert_eq!(to_srgb(0.5), 188);
+    }
+
+    #[test]
+    fn single_sample_is_taken_in_the_corner() {
+        // Corner of the pixel 2 is at -0.5
+        assert_eq!(
+            colorize(Sampling::Single).0[..5],
+            [BLACK, BLACK, BLACK, RED, RED]
+        );
+    }
+
+    #[test]
+    fn edge_pixel_is_averaged() {
+        for sampling in [Sampling::Grid(2), Sampling::Grid(4), Sampling::Jittered(2)] {
+            let (colors, stats) = colorize(sampling);
+
+            assert_eq!(
+                colors[..5],
+                [BLACK, BLACK, HALF, RED, RED],
+                "{:?}",
+                sampling
+            );
+            assert_eq!(colors[..5], colors[5..]);
+            assert_eq!(stats.refined, 0);
+        }
+    }
+
+    #[test]
+    fn adaptive_samples_only_around_edges() {
+        // Pixels 2 and 3 have both black and red around, in both rows
+        let (colors, stats) = colorize(Sampling::Adaptive(2));
+        assert_eq!(colors[..5], [BLACK, BLACK, HALF, RED, RED]);
+        assert_eq!(colors[..5], colors[5..]);
+        assert_eq!(stats.refined, 4);
+    }
+}